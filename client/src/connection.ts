@@ -1,18 +1,63 @@
+import { VERSION } from "./protocol";
+
 let secure = (window.location.protocol.match(/s/g) || "").toString();
-export let ws = new WebSocket(`ws${secure}://${window.location.host}/ws`);
+export let ws: WebSocket;
 export let opened = false;
 
+// The server hands out a token with which a dropped connection can take its
+// player back for a while
+let token: string | undefined;
+let handler: (e: MessageEvent) => void = () => {};
+
+export function resumeWith(t: string) {
+  token = t;
+}
+
+export function onMessage(h: (e: MessageEvent) => void) {
+  handler = h;
+}
+
+// Inputs are numbered so snapshots can say which ones they include, the ones
+// that are not in yet are kept for prediction
+let inputSeq = 0;
+export let pending: { seq: number; m: any }[] = [];
+
 export function send(m: any) {
-  if (opened) ws.send(JSON.stringify(m));
+  if (!opened) return;
+  inputSeq++;
+  pending.push({ seq: inputSeq, m });
+  ws.send(JSON.stringify({ seq: inputSeq, ...m }));
+}
+
+// Tell the server we have snapshot `seq`, it sends later ones as deltas
+// against it
+export function ack(seq: number) {
+  if (!opened) return;
+  ws.send(JSON.stringify({ Ack: seq }));
+}
+
+// Forget the inputs the server has applied as of a snapshot
+export function applied(input: number) {
+  pending = pending.filter((p) => p.seq > input);
 }
 
 const connError = document.getElementById("error");
-ws.onopen = () => {
-  opened = true;
-  document.getElementById("status").innerText = "Press enter to play";
-  connError.style.visibility = "hidden";
-};
-ws.onclose = () => {
-  opened = false;
-  connError.style.visibility = "visible";
-};
+function connect() {
+  ws = new WebSocket(`ws${secure}://${window.location.host}/ws`);
+  ws.binaryType = "arraybuffer";
+  ws.onmessage = (e) => handler(e);
+  ws.onopen = () => {
+    ws.send(JSON.stringify({ Hello: { version: VERSION, encodings: ["binary", "json"], resume: token } }));
+    opened = true;
+    document.getElementById("status").innerText = "Press enter to play";
+    connError.style.visibility = "hidden";
+  };
+  ws.onclose = (e) => {
+    opened = false;
+    connError.style.visibility = "visible";
+    connError.innerText = e.reason || "There was a connection error";
+    // A normal close means another connection took the session over
+    if (token && e.code != 1000) setTimeout(connect, 1000);
+  };
+}
+connect();
@@ -2,22 +2,29 @@ import * as THREE from "three";
 import { send, opened } from "./connection";
 import { camera } from "./scene";
 const classSelector = document.getElementById("class-selector");
-let classes = ["Quickshot", "Sniper"];
+let classes: string[] = [];
 let selected = 0;
-for (let i in classes) {
-  const newDiv = document.createElement("img");
-  newDiv.src = "/img/spaceCraft" + (+i * 2 + 1) + ".png";
-  newDiv.classList.add("circle");
-  if (+i == selected) newDiv.classList.add("selected");
-  newDiv.addEventListener("click", () => {
-    let els = document.querySelectorAll(".selected");
-    for (let j = 0; j < els.length; j++) {
-      els[j].classList.remove("selected");
-    }
-    selected = +i;
-    newDiv.classList.add("selected");
-  });
-  classSelector.appendChild(newDiv);
+// Offer the classes the server has, looking like their bullet pattern
+export function showClasses(infos: any[]) {
+  classes = infos.map((c) => c.class);
+  selected = Math.min(selected, classes.length - 1);
+  classSelector.innerHTML = "";
+  for (let i in infos) {
+    const newDiv = document.createElement("img");
+    newDiv.src = "/img/spaceCraft" + (infos[i].pattern == "sunflower" ? 1 : 3) + ".png";
+    newDiv.title = infos[i].class;
+    newDiv.classList.add("circle");
+    if (+i == selected) newDiv.classList.add("selected");
+    newDiv.addEventListener("click", () => {
+      let els = document.querySelectorAll(".selected");
+      for (let j = 0; j < els.length; j++) {
+        els[j].classList.remove("selected");
+      }
+      selected = +i;
+      newDiv.classList.add("selected");
+    });
+    classSelector.appendChild(newDiv);
+  }
 }
 document.getElementById("username").focus();
 document.getElementById("username").addEventListener("keydown", (e) => {
@@ -62,6 +69,10 @@ window.addEventListener("keydown", (e) => {
   if (e.keyCode == 27) {
     send({ Escape: true });
   }
+  // 1 to 3 hold the abilities of the class
+  if (e.keyCode >= 49 && e.keyCode <= 51 && !e.repeat) {
+    send({ Ability: [e.keyCode - 49, true] });
+  }
 });
 window.addEventListener("keyup", (e) => {
   if (e.keyCode == 83) {
@@ -78,6 +89,9 @@ window.addEventListener("keyup", (e) => {
   if (e.keyCode == 27) {
     send({ Escape: false });
   }
+  if (e.keyCode >= 49 && e.keyCode <= 51) {
+    send({ Ability: [e.keyCode - 49, false] });
+  }
 });
 window.addEventListener("mousedown", (e) => {
   if (e.button == 0) {
@@ -3,11 +3,12 @@ import { EffectComposer } from "three/examples/jsm/postprocessing/EffectComposer
 import { UnrealBloomPass } from "three/examples/jsm/postprocessing/UnrealBloomPass.js";
 import { RenderPass } from "three/examples/jsm/postprocessing/RenderPass.js";
 import { ShaderPass } from "three/examples/jsm/postprocessing/ShaderPass.js";
-import { models } from "./loader";
-import { scene, renderer, camera } from "./scene";
+import { models, whenLoaded } from "./loader";
+import { scene, renderer, camera, setArena } from "./scene";
 import { createText } from "./text";
-import { ws } from "./connection";
-import "./controls";
+import { onMessage, applied, resumeWith, ack } from "./connection";
+import { decode, setClasses, classInfo, remember, forgetBaselines, patch } from "./protocol";
+import { showClasses } from "./controls";
 import Stats from "stats.js";
 
 let stats = new Stats();
@@ -19,6 +20,10 @@ let bulletMaterial = new THREE.MeshLambertMaterial({ color: 0x81e1fc });
 let healthMaterial = new THREE.MeshLambertMaterial({ color: 0x3fab00 });
 let manaMaterial = new THREE.MeshLambertMaterial({ color: 0x00bbff });
 let cooldownMaterial = new THREE.MeshLambertMaterial({ color: 0x694129 });
+let shieldMaterial = new THREE.MeshLambertMaterial({ color: 0x81e1fc, transparent: true, opacity: 0.4 });
+let turretGeometry = new THREE.OctahedronBufferGeometry(20);
+let turretMaterial = new THREE.MeshLambertMaterial({ color: 0x3fab00 });
+let obstacleMaterial = new THREE.MeshLambertMaterial({ color: 0x5a4a42 });
 
 let wormholegeometry = new THREE.IcosahedronBufferGeometry(50, 3);
 let wormholematerials = [0x00ff00, 0xffff00, 0xff0000].map((c) => new THREE.MeshLambertMaterial({ color: c }));
@@ -37,9 +42,22 @@ bloomComposer.addPass(bloomPass);
 
 const sprites: { [key: string]: THREE.Object3D } = {};
 const bullets: { [key: string]: THREE.Mesh } = {};
+let turrets: THREE.Mesh[] = [];
+let obstacles: THREE.Object3D[] = [];
 let wormholes: THREE.Mesh[] = [];
 let boss: THREE.Object3D | undefined = undefined;
 
+// The arena we are in, on a torus things are drawn on whichever side of the
+// seam is closest to the camera
+let arena = { size: [1600, 1600], geometry: "rectangle" };
+function near(pos: number[]): [number, number] {
+  if (arena.geometry != "torus") return [pos[0], pos[1]];
+  let [w, h] = arena.size;
+  let x = pos[0] + Math.round((camera.position.x - pos[0]) / w) * w;
+  let y = pos[1] + Math.round((camera.position.y - pos[1]) / h) * h;
+  return [x, y];
+}
+
 let uiElements: THREE.Mesh[] = [];
 let uiMaterials: (THREE.Material | THREE.Material[])[] = [];
 let darkMaterial = new THREE.MeshBasicMaterial({ color: 0x000000 });
@@ -108,10 +126,19 @@ function draw() {
 }
 draw();
 
-ws.onmessage = (e) => {
-  const m = JSON.parse(e.data);
-  if (m.you) myid = m.you;
-  if (m.death) {
+onMessage((e) => {
+  const m = e.data instanceof ArrayBuffer ? decode(e.data) : JSON.parse(e.data);
+  if (m.welcome) {
+    if (myid && m.welcome.you != myid) {
+      // Too late to take the old player back
+      document.getElementById("login").style.display = "block";
+    }
+    myid = m.welcome.you;
+    resumeWith(m.welcome.token);
+    setClasses(m.welcome.classes);
+    showClasses(m.welcome.classes);
+  }
+  if (m.death && sprites[m.death]) {
     let group = sprites[m.death];
     for (let i = group.children.length - 1; i >= 0; i--) {
       group.remove(group.children[i]);
@@ -127,6 +154,7 @@ ws.onmessage = (e) => {
     }
   }
   if (m.clear) {
+    forgetBaselines();
     for (let w of wormholes) {
       scene.remove(w);
     }
@@ -144,6 +172,24 @@ ws.onmessage = (e) => {
       scene.remove(bullets[b]);
       delete sprites[b];
     }
+    for (let t of turrets) {
+      scene.remove(t);
+    }
+    turrets = [];
+    for (let o of obstacles) {
+      scene.remove(o);
+    }
+    obstacles = [];
+  }
+  if (m.arena) {
+    arena = m.arena;
+    setArena(m.arena.size, m.arena.geometry);
+  }
+  if (m.obstacles) {
+    for (let o of obstacles) {
+      scene.remove(o);
+    }
+    obstacles = m.obstacles.map(drawObstacle);
   }
   if (m.wormhole) {
     let sphere = new THREE.Mesh(wormholegeometry, wormholematerials[m.wormhole.color]);
@@ -152,33 +198,85 @@ ws.onmessage = (e) => {
     scene.add(sphere);
     wormholes.push(sphere);
   }
+  // Deltas are patched onto the snapshot they are based on, and every
+  // snapshot we have is acknowledged so the next can be a delta against it
+  let playfield = m.delta ? patch(m.delta) : m.playfield;
+  if (playfield) {
+    remember(playfield);
+    drawPlayfield(playfield);
+    ack(playfield.seq);
+  }
+});
+
+// The outline players bump into, with the model on top once it is loaded
+function drawObstacle(o: any): THREE.Object3D {
+  let group = new THREE.Group();
+  let geometry: THREE.BufferGeometry;
+  let size: number;
+  if (o.shape.circle) {
+    size = o.shape.circle;
+    geometry = new THREE.CircleBufferGeometry(size, 24);
+  } else {
+    let points = o.shape.polygon.map((p: number[]) => new THREE.Vector2(p[0], p[1]));
+    size = Math.max(...points.map((p: THREE.Vector2) => p.length()));
+    geometry = new THREE.ShapeBufferGeometry(new THREE.Shape(points));
+  }
+  group.add(new THREE.Mesh(geometry, obstacleMaterial));
+  whenLoaded(() => {
+    if (!models[o.model]) return;
+    let mesh = models[o.model].clone();
+    mesh.scale.setScalar(size * 10);
+    mesh.rotation.x = Math.PI / 2;
+    group.add(mesh);
+  });
+  group.position.x = o.pos[0];
+  group.position.y = o.pos[1];
+  scene.add(group);
+  return group;
+}
+
+function drawPlayfield(m: any) {
+  // The camera follows us, before anything is placed around it
+  let me = m.players && m.players.find((p: any) => p.id == myid);
+  if (me) {
+    camera.position.x = me.pos[0];
+    camera.position.y = me.pos[1];
+  }
   if (m.boss) {
     if (!boss) {
       let obj = new THREE.Object3D();
       let sphere = new THREE.Mesh(wormholegeometry, bossmaterial);
       let health = new THREE.Mesh(bosshealthgeometry, bosshealthmaterial);
 
+      let shield = new THREE.Mesh(new THREE.CircleBufferGeometry(80, 24), shieldMaterial);
+
       health.position.y += 70;
       obj.add(sphere);
       obj.add(health);
+      obj.add(shield);
       scene.add(obj);
       boss = obj;
     }
     boss.children[1].scale.x = m.boss.health / 255;
-    boss.position.x = m.boss.pos[0];
-    boss.position.y = m.boss.pos[1];
+    boss.children[2].visible = m.boss.shield;
+    [boss.position.x, boss.position.y] = near(m.boss.pos);
   } else if (boss) {
-    boss.remove(boss.children[1]);
-    boss.remove(boss.children[0]);
+    for (let i = boss.children.length - 1; i >= 0; i--) {
+      boss.remove(boss.children[i]);
+    }
     scene.remove(boss);
     boss = undefined;
   }
   if (m.players) {
+    applied(m.input);
+    // Players only show up while they are near us
+    let seen: { [key: string]: boolean } = {};
     m.players.forEach((p: any) => {
+      seen[p.id] = true;
       if (!sprites[p.id]) {
         let container = new THREE.Group();
         {
-          let c = p.class == "Quickshot";
+          let c = classInfo(p.class).pattern == "sunflower";
           let mesh = models[c ? "spaceCraft1" : "spaceCraft3"].clone();
           ((mesh.children[c ? 1 : 0] as THREE.Mesh).material as THREE.MeshStandardMaterial).color.setHex(0x00ff00);
 
@@ -207,6 +305,11 @@ ws.onmessage = (e) => {
           mesh.position.set(0, 0, 0);
           container.add(mesh);
         }
+        {
+          let mesh = new THREE.Mesh(new THREE.CircleBufferGeometry(60, 24), shieldMaterial);
+          mesh.visible = false;
+          container.add(mesh);
+        }
         if (p.name) {
           let text = createText(p.name, 30);
           uiElements.push(text);
@@ -218,12 +321,7 @@ ws.onmessage = (e) => {
         scene.add(container);
         sprites[p.id] = container;
       }
-      if (p.id == myid) {
-        camera.position.x = p.pos[0];
-        camera.position.y = p.pos[1];
-      }
-      sprites[p.id].position.x = p.pos[0];
-      sprites[p.id].position.y = p.pos[1];
+      [sprites[p.id].position.x, sprites[p.id].position.y] = near(p.pos);
 
       {
         let ring = new THREE.RingBufferGeometry(68, 80, 10, 1, Math.PI * (1.5 - p.health / 510), (Math.PI * p.health) / 510);
@@ -236,7 +334,7 @@ ws.onmessage = (e) => {
         (sprites[p.id].children[2] as THREE.Mesh).geometry = ring;
       }
       {
-        let cooldown = p.class == "Quickshot" ? 750 : 1000;
+        let cooldown = classInfo(p.class).shot_cooldown;
         let timeDiff = Math.min(p.shot_time, cooldown);
         if (timeDiff == 0) {
           sprites[p.id].children[3].visible = false;
@@ -247,8 +345,37 @@ ws.onmessage = (e) => {
           (sprites[p.id].children[3] as THREE.Mesh).geometry = ring;
         }
       }
+      {
+        // The ring around the player while one of its shields is up, or
+        // while it was just spawned
+        let abilities = classInfo(p.class).abilities || [];
+        sprites[p.id].children[4].visible =
+          p.invulnerable > 0 ||
+          (p.abilities || []).some((a: any, i: number) => a.active > 0 && abilities[i]?.kind == "shield");
+      }
       sprites[p.id].children[0].rotation.y = -p.angle;
     });
+    for (let id in sprites) {
+      if (!seen[id]) {
+        let group = sprites[id];
+        for (let i = group.children.length - 1; i >= 0; i--) {
+          group.remove(group.children[i]);
+        }
+        scene.remove(group);
+        delete sprites[id];
+      }
+    }
+  }
+  if (m.turrets) {
+    for (let t of turrets) {
+      scene.remove(t);
+    }
+    turrets = m.turrets.map((t: any) => {
+      let mesh = new THREE.Mesh(turretGeometry, turretMaterial);
+      [mesh.position.x, mesh.position.y] = near(t.pos);
+      scene.add(mesh);
+      return mesh;
+    });
   }
   if (m.bullets) {
     let marked: { [key: string]: boolean } = {};
@@ -263,8 +390,7 @@ ws.onmessage = (e) => {
         mesh.rotation.z = Math.PI - Math.atan2(b.vel[0], b.vel[1]);
         bullets[b.id] = mesh;
       }
-      bullets[b.id].position.x = b.pos[0];
-      bullets[b.id].position.y = b.pos[1];
+      [bullets[b.id].position.x, bullets[b.id].position.y] = near(b.pos);
       marked[b.id] = true;
     });
     for (let key in bullets) {
@@ -274,4 +400,4 @@ ws.onmessage = (e) => {
       }
     }
   }
-};
+}
@@ -29,7 +29,19 @@ export const models: { [key: string]: THREE.Object3D } = {
   }
 }
 
+let loaded = false;
+let waiting: (() => void)[] = [];
+
+// Run `f` once all models are loaded, right away if they are
+export function whenLoaded(f: () => void) {
+  if (loaded) f();
+  else waiting.push(f);
+}
+
 function init() {
   console.log("loaded");
   addGround();
+  loaded = true;
+  for (const f of waiting) f();
+  waiting = [];
 }
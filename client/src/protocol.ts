@@ -0,0 +1,221 @@
+// Decoder for the binary frames described in src/protocol.rs
+export const VERSION = 8;
+const KIND_PLAYFIELD = 0;
+const KIND_DELTA = 1;
+
+const POS_SCALE = 4;
+const VEL_SCALE = 64;
+
+const PLAYFIELD_BOSS = 1 << 0;
+const PLAYFIELD_BOSS_SHIELD = 1 << 1;
+
+const DELTA_POS = 1 << 0;
+const DELTA_ANGLE = 1 << 1;
+const DELTA_HEALTH = 1 << 2;
+const DELTA_MANA = 1 << 3;
+const DELTA_SHOT_TIME = 1 << 4;
+const DELTA_NAME = 1 << 5;
+const DELTA_CLASS = 1 << 6;
+const DELTA_ABILITIES = 1 << 7;
+const DELTA_INVULNERABLE = 1 << 8;
+
+const utf8 = new TextDecoder();
+
+// The classes from the welcome, binary frames send their index in it
+export let classes: any[] = [];
+export function setClasses(c: any[]) {
+  classes = c;
+}
+export function classInfo(name: string): any {
+  return classes.find((c) => c.class == name) || classes[0];
+}
+
+class Reader {
+  offset = 0;
+  constructor(private view: DataView) {}
+
+  u8(): number {
+    return this.view.getUint8(this.offset++);
+  }
+  u16(): number {
+    let v = this.view.getUint16(this.offset, true);
+    this.offset += 2;
+    return v;
+  }
+  varint(): number {
+    let v = 0;
+    let scale = 1;
+    for (;;) {
+      let b = this.u8();
+      v += (b & 0x7f) * scale;
+      if (b < 0x80) return v;
+      scale *= 128;
+    }
+  }
+  zigzag(): number {
+    let v = this.varint();
+    return v % 2 == 0 ? v / 2 : -(v + 1) / 2;
+  }
+  vector(scale: number): [number, number] {
+    return [this.zigzag() / scale, this.zigzag() / scale];
+  }
+  angle(): number {
+    let turn = this.u16() / 65536;
+    return turn > 0.5 ? (turn - 1) * 2 * Math.PI : turn * 2 * Math.PI;
+  }
+  string(): string {
+    let len = this.varint();
+    let s = utf8.decode(new Uint8Array(this.view.buffer, this.view.byteOffset + this.offset, len));
+    this.offset += len;
+    return s;
+  }
+}
+
+// Turns a binary frame into the same shape as the JSON messages
+export function decode(data: ArrayBuffer): any {
+  let r = new Reader(new DataView(data));
+  if (r.u8() != VERSION) throw new Error("Unsupported protocol version");
+  let kind = r.u8();
+  if (kind == KIND_PLAYFIELD) return { playfield: decodePlayfield(r) };
+  if (kind == KIND_DELTA) return { delta: decodeDelta(r) };
+  throw new Error("Unknown frame kind " + kind);
+}
+
+function decodePlayfield(r: Reader): any {
+  let seq = r.varint();
+  let tick = r.varint();
+  let input = r.varint();
+  let flags = r.u8();
+  let players = [];
+  for (let n = r.varint(); n > 0; n--) {
+    let id = r.varint();
+    let pos = r.vector(POS_SCALE);
+    let angle = r.angle();
+    let health = r.u8();
+    let mana = r.u8();
+    let class_ = classes[r.u8()]?.class;
+    let shot_time = r.varint();
+    let name = r.string();
+    let abilities = slots(r);
+    let invulnerable = r.varint();
+    players.push({ id, pos, name, angle, health, mana, class: class_, shot_time, abilities, invulnerable });
+  }
+  let bullets = [];
+  for (let n = r.varint(); n > 0; n--) {
+    bullets.push(bullet(r));
+  }
+  let turrets = decodeTurrets(r);
+  let boss = decodeBoss(r, flags);
+  return { seq, tick, input, players, bullets, turrets, boss };
+}
+
+function decodeDelta(r: Reader): any {
+  let seq = r.varint();
+  let base = r.varint();
+  let tick = r.varint();
+  let input = r.varint();
+  let flags = r.u8();
+  let players = [];
+  for (let n = r.varint(); n > 0; n--) {
+    // Only the fields in the mask are there, in this order
+    let p: any = { id: r.varint() };
+    let mask = r.varint();
+    if (mask & DELTA_POS) p.pos = r.vector(POS_SCALE);
+    if (mask & DELTA_ANGLE) p.angle = r.angle();
+    if (mask & DELTA_HEALTH) p.health = r.u8();
+    if (mask & DELTA_MANA) p.mana = r.u8();
+    if (mask & DELTA_SHOT_TIME) p.shot_time = r.varint();
+    if (mask & DELTA_NAME) p.name = r.string();
+    if (mask & DELTA_CLASS) p.class = classes[r.u8()]?.class;
+    if (mask & DELTA_ABILITIES) p.abilities = slots(r);
+    if (mask & DELTA_INVULNERABLE) p.invulnerable = r.varint();
+    players.push(p);
+  }
+  let removed = [];
+  for (let n = r.varint(); n > 0; n--) {
+    removed.push(r.varint());
+  }
+  let spawned = [];
+  for (let n = r.varint(); n > 0; n--) {
+    spawned.push(bullet(r));
+  }
+  let despawned = [];
+  for (let n = r.varint(); n > 0; n--) {
+    despawned.push(r.varint());
+  }
+  let turrets = decodeTurrets(r);
+  let boss = decodeBoss(r, flags);
+  return { seq, base, tick, input, players, removed, spawned, despawned, turrets, boss };
+}
+
+function slots(r: Reader): any[] {
+  let abilities = [];
+  for (let k = r.varint(); k > 0; k--) {
+    abilities.push({ cooldown: r.varint(), active: r.varint() });
+  }
+  return abilities;
+}
+
+function bullet(r: Reader): any {
+  let id = r.varint();
+  let pos = r.vector(POS_SCALE);
+  let vel = r.vector(VEL_SCALE);
+  return { vel, pos, id };
+}
+
+function decodeTurrets(r: Reader): any[] {
+  let turrets = [];
+  for (let n = r.varint(); n > 0; n--) {
+    let pos = r.vector(POS_SCALE);
+    turrets.push({ pos, owner: r.varint() });
+  }
+  return turrets;
+}
+
+function decodeBoss(r: Reader, flags: number): any {
+  if (!(flags & PLAYFIELD_BOSS)) return null;
+  let pos = r.vector(POS_SCALE);
+  return { pos, health: r.u8(), shield: (flags & PLAYFIELD_BOSS_SHIELD) != 0 };
+}
+
+// Recent snapshots by sequence number, deltas are sent against one of them
+const BASELINES = 64;
+let baselines = new Map<number, any>();
+
+export function remember(playfield: any) {
+  baselines.set(playfield.seq, playfield);
+  baselines.delete(playfield.seq - BASELINES);
+}
+
+// Snapshots of another arena are no base for this one
+export function forgetBaselines() {
+  baselines.clear();
+}
+
+// The full snapshot a delta describes, or null if we don't have its base.
+// Bullets that kept their velocity are moved on from where they were.
+export function patch(delta: any): any {
+  let base = baselines.get(delta.base);
+  if (!base) return null;
+
+  let players = new Map<number, any>(base.players.map((p: any) => [p.id, p]));
+  for (let id of delta.removed) {
+    players.delete(id);
+  }
+  for (let p of delta.players) {
+    players.set(p.id, { ...players.get(p.id), ...p });
+  }
+
+  let ticks = delta.tick - base.tick;
+  let gone = new Set<number>(delta.despawned);
+  for (let b of delta.spawned) {
+    gone.add(b.id);
+  }
+  let bullets = base.bullets
+    .filter((b: any) => !gone.has(b.id))
+    .map((b: any) => ({ ...b, pos: [b.pos[0] + b.vel[0] * ticks, b.pos[1] + b.vel[1] * ticks] }))
+    .concat(delta.spawned);
+
+  let { seq, tick, input, turrets, boss } = delta;
+  return { seq, tick, input, players: Array.from(players.values()), bullets, turrets, boss };
+}
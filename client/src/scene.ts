@@ -11,18 +11,32 @@ const WORLDSIZE = 1600;
   scene.background = new THREE.Color(color);
 }
 
-{
+let border: THREE.Line;
+// Outline the arena, which is `size` across. Circle arenas are the largest
+// circle that fits, the edges of a torus are where it wraps around.
+export function setArena(size: [number, number], geometry: string = "rectangle") {
+  if (border) scene.remove(border);
+  let [width, height] = size;
   let material = new THREE.LineBasicMaterial({ color: 0x111111 });
   let points = [];
-  points.push(new THREE.Vector3(0, 0, 0));
-  points.push(new THREE.Vector3(0, WORLDSIZE, 0));
-  points.push(new THREE.Vector3(WORLDSIZE, WORLDSIZE, 0));
-  points.push(new THREE.Vector3(WORLDSIZE, 0, 0));
-  points.push(new THREE.Vector3(0, 0, 0));
+  if (geometry == "circle") {
+    let radius = Math.min(width, height) / 2;
+    for (let i = 0; i <= 64; i++) {
+      let angle = (i / 64) * 2 * Math.PI;
+      points.push(new THREE.Vector3(width / 2 + Math.cos(angle) * radius, height / 2 + Math.sin(angle) * radius, 0));
+    }
+  } else {
+    points.push(new THREE.Vector3(0, 0, 0));
+    points.push(new THREE.Vector3(0, height, 0));
+    points.push(new THREE.Vector3(width, height, 0));
+    points.push(new THREE.Vector3(width, 0, 0));
+    points.push(new THREE.Vector3(0, 0, 0));
+  }
   let geometry = new THREE.BufferGeometry().setFromPoints(points);
-  let line = new THREE.Line(geometry, material);
-  scene.add(line);
+  border = new THREE.Line(geometry, material);
+  scene.add(border);
 }
+setArena([WORLDSIZE, WORLDSIZE]);
 
 {
   let light = new THREE.AmbientLight(0x404040);
@@ -0,0 +1,329 @@
+//! Load generator for the game server.
+//!
+//! Opens many bot connections to `/ws`, spawns them with random names and
+//! classes and drives them with random inputs. Unless `--join` is given they
+//! stay out of wormholes so they all measure the home arena. Every second it
+//! prints what the bots receive: throughput, snapshot sizes, how fast the
+//! server ticks and how unevenly its snapshots arrive, and how many bots were
+//! dropped.
+//! A server that keeps up ticks `1000 / TICK_MILLIS` times a second.
+//!
+//! `cargo run --release -p loadgen -- [URL] [--clients N] [--seconds S]
+//! [--ramp MS] [--encoding json|binary] [--ack] [--join]`
+use futures_util::{SinkExt, StreamExt};
+use rand::prelude::*;
+use s3::protocol::VERSION;
+use s3_sim::consts::TICK_MILLIS;
+use s3_sim::Input;
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::{Error, Message};
+
+struct Options {
+    url: String,
+    /// Number of bots
+    clients: usize,
+    /// How long to run for
+    seconds: u64,
+    /// Delay between two bots connecting
+    ramp: Duration,
+    encoding: String,
+    /// Acknowledge snapshots so the server sends deltas
+    ack: bool,
+    /// Hold join now and then, taking bots into other arenas
+    join: bool,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Options, String> {
+        let mut options = Options {
+            url: "ws://localhost:8080/ws".to_owned(),
+            clients: 100,
+            seconds: 30,
+            ramp: Duration::from_millis(10),
+            encoding: "binary".to_owned(),
+            ack: false,
+            join: false,
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--clients" => options.clients = value()?.parse().map_err(|_| "bad --clients")?,
+                "--seconds" => options.seconds = value()?.parse().map_err(|_| "bad --seconds")?,
+                "--ramp" => {
+                    options.ramp =
+                        Duration::from_millis(value()?.parse().map_err(|_| "bad --ramp")?)
+                }
+                "--encoding" => options.encoding = value()?.clone(),
+                "--ack" => options.ack = true,
+                "--join" => options.join = true,
+                url if !url.starts_with("--") => options.url = url.to_owned(),
+                _ => return Err(format!("unknown option {}", arg)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// What the bots saw since the last report, and a few running totals
+#[derive(Default)]
+struct Stats {
+    /// Bots that are in the game
+    connected: usize,
+    /// Bots whose connection ended
+    disconnects: usize,
+    /// Highest server tick seen
+    tick: u64,
+
+    messages: usize,
+    bytes: usize,
+    snapshots: usize,
+    snapshot_bytes: usize,
+    max_snapshot: usize,
+    inputs: usize,
+    /// How far the time between two snapshots of a bot was off from the
+    /// ticks between them, in microseconds
+    jitter: Vec<u64>,
+}
+
+impl Stats {
+    /// One line about the last `elapsed`, and start a new window. `since_tick`
+    /// is the highest tick at the start of the window, if any was seen yet.
+    fn report(&mut self, elapsed: Duration, since_tick: Option<u64>) -> String {
+        let secs = elapsed.as_secs_f64();
+        self.jitter.sort_unstable();
+        let percentile = |p: f64| {
+            let i = ((self.jitter.len() as f64 * p) as usize).min(self.jitter.len().max(1) - 1);
+            self.jitter.get(i).map_or(0.0, |&us| us as f64 / 1000.0)
+        };
+        let line = format!(
+            "clients {:>5}  in {:>7.0} msg/s {:>8.1} KiB/s  out {:>6.0} inputs/s  \
+             snapshot avg {:>5} B max {:>6} B  tick {:>5.1}/s  \
+             jitter p50 {:>5.1} ms p99 {:>5.1} ms  disconnects {}",
+            self.connected,
+            self.messages as f64 / secs,
+            self.bytes as f64 / 1024.0 / secs,
+            self.inputs as f64 / secs,
+            self.snapshot_bytes.checked_div(self.snapshots).unwrap_or(0),
+            self.max_snapshot,
+            since_tick.map_or(0, |t| self.tick - t) as f64 / secs,
+            percentile(0.5),
+            percentile(0.99),
+            self.disconnects,
+        );
+        *self = Stats {
+            connected: self.connected,
+            disconnects: self.disconnects,
+            tick: self.tick,
+            ..Stats::default()
+        };
+        line
+    }
+
+    /// Count a snapshot a bot got at `at`, `last` is the tick and time of its
+    /// previous one
+    fn snapshot(&mut self, len: usize, tick: u64, at: Instant, last: Option<(u64, Instant)>) {
+        self.snapshots += 1;
+        self.snapshot_bytes += len;
+        self.max_snapshot = self.max_snapshot.max(len);
+        self.tick = self.tick.max(tick);
+        if let Some((last_tick, last_at)) = last {
+            let expected = tick.saturating_sub(last_tick) * TICK_MILLIS * 1000;
+            let actual = at.duration_since(last_at).as_micros() as u64;
+            self.jitter.push(actual.abs_diff(expected));
+        }
+    }
+}
+
+fn varint(bytes: &mut impl Iterator<Item = u8>) -> Option<u64> {
+    let mut v = 0;
+    for shift in (0..64).step_by(7) {
+        let b = bytes.next()?;
+        v |= ((b & 0x7f) as u64) << shift;
+        if b < 0x80 {
+            return Some(v);
+        }
+    }
+    None
+}
+
+/// Sequence number and tick of a binary snapshot
+fn binary_header(frame: &[u8]) -> Option<(u64, u64)> {
+    let mut bytes = frame.iter().copied();
+    let _version = bytes.next()?;
+    let kind = bytes.next()?;
+    let seq = varint(&mut bytes)?;
+    if kind == 1 {
+        // Deltas have the sequence number of their base next
+        varint(&mut bytes)?;
+    }
+    Some((seq, varint(&mut bytes)?))
+}
+
+/// Any input, joining wormholes only if `join` since bots that stay in the
+/// arena under test see the ticks of one server
+fn random_input(rng: &mut StdRng, join: bool) -> Input {
+    match rng.gen_range(0..if join { 11 } else { 10 }) {
+        0..=4 => Input::Target([rng.gen_range(-500.0..500.0), rng.gen_range(-500.0..500.0)].into()),
+        5..=7 => Input::Click(rng.gen()),
+        8 => Input::Split(rng.gen()),
+        9 => Input::Ability(rng.gen_range(0..3), rng.gen()),
+        _ => Input::Join(rng.gen()),
+    }
+}
+
+/// Play one bot until its connection ends, `welcomed` is set once it counts
+/// as connected
+async fn bot(
+    i: usize,
+    options: Arc<Options>,
+    stats: Arc<Mutex<Stats>>,
+    welcomed: &mut bool,
+) -> Result<(), Error> {
+    let (mut ws, _) = connect_async(options.url.as_str()).await?;
+    let hello = json!({ "Hello": { "version": VERSION, "encodings": [options.encoding] } });
+    ws.send(Message::Text(hello.to_string())).await?;
+
+    let mut rng = StdRng::from_entropy();
+    let name = format!("bot{}", i);
+    let mut class = String::new();
+    let mut you = None;
+    let mut seq = 0;
+    let mut last = None;
+    let mut inputs = tokio::time::interval(Duration::from_millis(rng.gen_range(100..300)));
+
+    loop {
+        let mut send = Vec::new();
+        let mut ack = None;
+        tokio::select! {
+            msg = ws.next() => {
+                let msg = match msg {
+                    Some(msg) => msg?,
+                    None => return Ok(()),
+                };
+                let at = Instant::now();
+                let mut stats = stats.lock().unwrap();
+                stats.messages += 1;
+                stats.bytes += msg.len();
+                let snapshot = match &msg {
+                    Message::Text(text) => {
+                        let m: Value = serde_json::from_str(text).unwrap_or_default();
+                        if let Some(welcome) = m.get("welcome") {
+                            you = Some(welcome["you"].clone());
+                            stats.connected += 1;
+                            *welcomed = true;
+                            let classes = welcome["classes"].as_array().cloned().unwrap_or_default();
+                            if let Some(c) = classes.choose(&mut rng) {
+                                class = c["class"].as_str().unwrap_or_default().to_owned();
+                            }
+                            send.push(Input::Spawn(name.clone(), class.clone()));
+                        } else if m.get("death").is_some() && m.get("death") == you.as_ref() {
+                            send.push(Input::Spawn(name.clone(), class.clone()));
+                        } else if m.get("clear").is_some() {
+                            // Ticks of another server don't follow on
+                            last = None;
+                        }
+                        m.get("playfield")
+                            .or_else(|| m.get("delta"))
+                            .and_then(|s| Some((s["seq"].as_u64()?, s["tick"].as_u64()?)))
+                    }
+                    Message::Binary(frame) => binary_header(frame),
+                    Message::Close(_) => return Ok(()),
+                    _ => None,
+                };
+                if let Some((snapshot_seq, tick)) = snapshot {
+                    stats.snapshot(msg.len(), tick, at, last);
+                    last = Some((tick, at));
+                    if options.ack {
+                        ack = Some(snapshot_seq);
+                    }
+                }
+            }
+            _ = inputs.tick(), if you.is_some() => send.push(random_input(&mut rng, options.join)),
+        }
+        if let Some(snapshot_seq) = ack {
+            let ack = json!({ "Ack": snapshot_seq });
+            ws.send(Message::Text(ack.to_string())).await?;
+        }
+        for input in send {
+            seq += 1;
+            let mut m = serde_json::to_value(&input).unwrap();
+            m["seq"] = json!(seq);
+            ws.send(Message::Text(m.to_string())).await?;
+            stats.lock().unwrap().inputs += 1;
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = match Options::parse(&args) {
+        Ok(options) => Arc::new(options),
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!(
+                "usage: loadgen [URL] [--clients N] [--seconds S] [--ramp MS] \
+                 [--encoding json|binary] [--ack] [--join]"
+            );
+            std::process::exit(2);
+        }
+    };
+    println!(
+        "{} bots against {} for {}s",
+        options.clients, options.url, options.seconds
+    );
+
+    let stats = Arc::new(Mutex::new(Stats::default()));
+    for i in 0..options.clients {
+        let (options, stats) = (options.clone(), stats.clone());
+        tokio::spawn(async move {
+            tokio::time::sleep(options.ramp * i as u32).await;
+            let mut welcomed = false;
+            let result = bot(i, options, stats.clone(), &mut welcomed).await;
+            let mut stats = stats.lock().unwrap();
+            stats.disconnects += 1;
+            if welcomed {
+                stats.connected -= 1;
+            }
+            if let Err(e) = result {
+                eprintln!("bot{} dropped: {}", i, e);
+            }
+        });
+    }
+
+    let start = Instant::now();
+    // The first tick seen and when, for the overall tick rate
+    let mut first: Option<(u64, Instant)> = None;
+    let mut last = (Instant::now(), None);
+    let mut report = tokio::time::interval(Duration::from_secs(1));
+    report.tick().await;
+    while start.elapsed() < Duration::from_secs(options.seconds) {
+        report.tick().await;
+        let now = Instant::now();
+        let mut stats = stats.lock().unwrap();
+        let tick = Some(stats.tick).filter(|&t| t > 0);
+        let line = stats.report(now - last.0, last.1);
+        println!("{:>4}s  {}", start.elapsed().as_secs(), line);
+        if let (None, Some(tick)) = (first, tick) {
+            first = Some((tick, now));
+        }
+        last = (now, tick);
+    }
+
+    let stats = stats.lock().unwrap();
+    let rate = first.map_or(0.0, |(tick, at)| {
+        (stats.tick - tick) as f64 / at.elapsed().as_secs_f64()
+    });
+    println!(
+        "done: {} of {} bots connected, {} disconnects, {:.1} ticks/s overall (target {:.1})",
+        stats.connected,
+        options.clients,
+        stats.disconnects,
+        rate,
+        1000.0 / TICK_MILLIS as f64,
+    );
+}
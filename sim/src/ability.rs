@@ -0,0 +1,100 @@
+//! Active abilities, paid for with mana.
+//!
+//! Every class has one to `MAX_ABILITIES` ability slots. While the key of a
+//! slot is held its ability is cast whenever it is off cooldown and the
+//! player has the mana for it. Boosts and shields last a while and are read
+//! off the `Slot`, blinks move the player at once, and heal pulses and
+//! turrets are left to the `World` since they affect more than the caster.
+use na::Vector2;
+use nalgebra as na;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::consts::ticks;
+
+/// Most abilities a class can have
+pub const MAX_ABILITIES: usize = 3;
+
+/// What casting an ability does
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Effect {
+    /// Fly `speed` times as fast for `duration` milliseconds
+    Boost { duration: u64, speed: f32 },
+    /// Take no damage for `duration` milliseconds
+    Shield { duration: u64 },
+    /// Jump `distance` towards the target
+    Blink { distance: f32 },
+    /// Deploy a turret for `duration` milliseconds, shooting the bullets of
+    /// the class at the nearest enemy within `range` every `rate`
+    /// milliseconds
+    Turret {
+        duration: u64,
+        range: f32,
+        rate: u64,
+    },
+    /// Heal by `amount`, and in arenas without pvp everyone within `radius`
+    /// too
+    Heal { radius: f32, amount: u8 },
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+pub struct Ability {
+    #[serde(flatten)]
+    pub effect: Effect,
+    /// Mana a cast costs
+    pub cost: u8,
+    /// Milliseconds between two casts
+    pub cooldown: u64,
+}
+
+impl Ability {
+    /// Ticks between two casts
+    pub fn recast(&self) -> u32 {
+        ticks(self.cooldown)
+    }
+    /// Ticks the effect lasts after a cast
+    pub fn duration(&self) -> u32 {
+        match self.effect {
+            Effect::Boost { duration, .. }
+            | Effect::Shield { duration }
+            | Effect::Turret { duration, .. } => ticks(duration),
+            Effect::Blink { .. } | Effect::Heal { .. } => 0,
+        }
+    }
+    /// The boost players had before there were abilities
+    pub fn boost() -> Ability {
+        Ability {
+            effect: Effect::Boost {
+                duration: 500,
+                speed: 3.0,
+            },
+            cost: 100,
+            cooldown: 600,
+        }
+    }
+}
+
+/// The state of an ability slot of a player
+#[derive(Deserialize, Serialize, Clone, Default, Debug)]
+pub struct Slot {
+    /// Whether its key is held
+    pub held: bool,
+    /// Ticks until it can be cast again
+    pub cooldown: u32,
+    /// Ticks its effect lasts for
+    pub active: u32,
+}
+
+/// A turret deployed by a player
+pub struct Turret {
+    pub id: usize,
+    pub pos: Vector2<f32>,
+    pub owner: usize,
+    pub range: f32,
+    /// Ticks between two shots
+    pub rate: u32,
+    /// Ticks until the next shot
+    pub reload: u32,
+    /// Ticks left before it disappears
+    pub ttl: u32,
+}
@@ -0,0 +1,571 @@
+//! Bosses, described by data instead of code.
+//!
+//! A boss fights in phases. It starts in the first one and moves on to the
+//! next once its health is down to the `health` of that phase, or when the
+//! phase it is in has a `duration`, once it has lasted that long. A phase
+//! says how the boss moves, whether it is shielded and which attacks it fires
+//! how often.
+//!
+//! A `Bosses` catalogue is read from TOML, a list of `[[boss]]` tables with
+//! the fields of `BossClass`. `Bosses::default()` is the one in `bosses.toml`
+//! next to this crate, servers can load another at startup.
+use na::{Rotation2, Vector2};
+use nalgebra as na;
+use rstar::{RTreeObject, AABB};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::iter::Peekable;
+use std::path::Path;
+
+use crate::consts::ticks;
+use crate::ids::IdGen;
+use crate::map::Map;
+use crate::player::Player;
+use crate::projectile::Behavior;
+
+/// How a boss gets around
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Movement {
+    /// Speed up by `acceleration` each tick towards where the nearest player
+    /// is going
+    Chase { acceleration: f32 },
+    /// Chase, and every `every` milliseconds rush at the nearest player at
+    /// `speed` for `duration` milliseconds
+    Charge {
+        acceleration: f32,
+        speed: f32,
+        every: u64,
+        duration: u64,
+    },
+    /// Drift to a stop
+    Hold,
+}
+
+/// How the bullets of an attack are laid out
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(tag = "pattern", rename_all = "lowercase")]
+pub enum Volley {
+    /// `bullets` at where the nearest player is going, fanned out over
+    /// `spread` degrees
+    Aimed {
+        #[serde(default = "one")]
+        bullets: u32,
+        #[serde(default)]
+        spread: f32,
+    },
+    /// A bullet `distance` to either side of the boss, drifting where an
+    /// aimed one would go at `drift` times its speed
+    Flank { distance: f32, drift: f32 },
+    /// `bullets` in a ring, turned by `turn` degrees from one volley to the
+    /// next
+    Spiral { bullets: u32, turn: f32 },
+    /// `count` minions in a ring that hunt down the nearest player within
+    /// `range`, turning by up to `turn` degrees a tick
+    Summon { count: u32, range: f32, turn: f32 },
+}
+
+fn one() -> u32 {
+    1
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Attack {
+    #[serde(flatten)]
+    pub volley: Volley,
+    /// Milliseconds between two volleys
+    pub every: u64,
+    /// Distance the bullets fly each tick
+    pub speed: f32,
+    /// Milliseconds the bullets fly for
+    #[serde(default = "default_lifetime")]
+    pub lifetime: u64,
+    /// How the bullets fly besides straight
+    #[serde(default)]
+    pub behaviors: Vec<Behavior>,
+}
+
+fn default_lifetime() -> u64 {
+    1250
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Phase {
+    /// The phase starts once the boss is down to this much health. Less than
+    /// the phase before, or as much if that one has a `duration`.
+    pub health: u8,
+    /// Milliseconds after which the next phase starts, whatever the health
+    /// of the boss. With 0 it lasts until the next phase is down to.
+    #[serde(default)]
+    pub duration: u64,
+    /// Whether the boss takes no damage
+    #[serde(default)]
+    pub shield: bool,
+    pub movement: Movement,
+    #[serde(default)]
+    pub attacks: Vec<Attack>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct BossClass {
+    pub name: String,
+    /// In the order the boss goes through them
+    pub phases: Vec<Phase>,
+}
+
+/// All the bosses arenas can have
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(transparent)]
+pub struct Bosses(Vec<BossClass>);
+
+/// The layout of a boss file
+#[derive(Deserialize)]
+struct BossFile {
+    boss: Vec<BossClass>,
+}
+
+fn invalid(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+impl Bosses {
+    /// Bosses from the text of a boss file
+    pub fn parse(text: &str) -> io::Result<Bosses> {
+        let file: BossFile = toml::from_str(text).map_err(invalid)?;
+        let bosses = Bosses(file.boss);
+        bosses.check()?;
+        Ok(bosses)
+    }
+    /// Bosses from a boss file
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Bosses> {
+        Bosses::parse(&fs::read_to_string(path)?)
+    }
+    /// Make sure a boss file describes bosses that can fight
+    fn check(&self) -> io::Result<()> {
+        if self.0.is_empty() {
+            return Err(invalid("no bosses"));
+        }
+        let mut names = HashSet::new();
+        for boss in &self.0 {
+            if !names.insert(&boss.name) {
+                return Err(invalid(format!("boss {} is there twice", boss.name)));
+            }
+            if boss.phases.is_empty() {
+                return Err(invalid(format!("boss {} needs a phase", boss.name)));
+            }
+            for phase in &boss.phases {
+                let charge_every = match phase.movement {
+                    Movement::Charge { every, .. } => every,
+                    _ => 1,
+                };
+                let empty = phase.attacks.iter().any(|a| match a.volley {
+                    Volley::Aimed { bullets, .. } | Volley::Spiral { bullets, .. } => bullets == 0,
+                    Volley::Summon { count, .. } => count == 0,
+                    Volley::Flank { .. } => false,
+                });
+                if charge_every == 0 || empty || phase.attacks.iter().any(|a| a.every == 0) {
+                    return Err(invalid(format!(
+                        "boss {} needs attacks and charges that take time, with bullets",
+                        boss.name
+                    )));
+                }
+            }
+            // A phase that ends on health hands over to one further down,
+            // otherwise the boss would skip straight past it
+            for pair in boss.phases.windows(2) {
+                let (phase, next) = (&pair[0], &pair[1]);
+                if next.health > phase.health
+                    || (phase.duration == 0 && next.health == phase.health)
+                {
+                    return Err(invalid(format!(
+                        "boss {} needs phases down to less and less health",
+                        boss.name
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &BossClass> {
+        self.0.iter()
+    }
+    /// The boss called `name`
+    pub fn get(&self, name: &str) -> Option<&BossClass> {
+        self.0.iter().find(|b| b.name == name)
+    }
+}
+
+impl Default for Bosses {
+    fn default() -> Bosses {
+        Bosses::parse(include_str!("../bosses.toml")).expect("bosses.toml is valid")
+    }
+}
+
+impl RTreeObject for &Boss {
+    type Envelope = AABB<[f32; 2]>;
+
+    fn envelope(&self) -> Self::Envelope {
+        let size = Boss::RADIUS;
+        AABB::from_corners(
+            [self.pos.x - size, self.pos.y - size],
+            [self.pos.x + size, self.pos.y + size],
+        )
+    }
+}
+impl Boss {
+    pub const RADIUS: f32 = 30.0;
+    /// How hard the boss is to push around, see `Player::MASS`
+    pub const MASS: f32 = 10.0;
+    /// How fast the boss expects to close in on who it chases
+    const LEAD: f32 = 10.0;
+
+    pub fn new(pos: Vector2<f32>, class: BossClass) -> Boss {
+        let mut boss = Boss {
+            pos,
+            vel: Vector2::new(0.0, 0.0),
+            health: 255,
+            class,
+            phase: 0,
+            phase_time: 0,
+            reloads: Vec::new(),
+            charge_reload: 0,
+            charging: 0,
+            spin: 0.0,
+        };
+        boss.enter(0);
+        boss
+    }
+}
+
+impl RTreeObject for &BossBullet {
+    type Envelope = AABB<[f32; 2]>;
+
+    fn envelope(&self) -> Self::Envelope {
+        let size = BossBullet::RADIUS;
+        AABB::from_corners(
+            [self.pos.x - size, self.pos.y - size],
+            [self.pos.x + size, self.pos.y + size],
+        )
+    }
+}
+
+impl BossBullet {
+    pub const RADIUS: f32 = 10.0;
+}
+pub struct Boss {
+    pub pos: Vector2<f32>,
+    pub vel: Vector2<f32>,
+    pub health: u8,
+    pub class: BossClass,
+    /// Which of the phases of `class` the boss is in
+    pub phase: usize,
+    /// Ticks the phase has lasted
+    phase_time: u32,
+    /// Ticks until each attack of the phase fires again
+    reloads: Vec<u32>,
+    /// Ticks until the next charge, and left of the current one
+    charge_reload: u32,
+    charging: u32,
+    /// Degrees rings of bullets are turned by
+    spin: f32,
+}
+
+fn intercept(a: Vector2<f32>, b: Vector2<f32>, u: Vector2<f32>, v_mag: f32) -> Vector2<f32> {
+    let ab = (b - a).normalize();
+    let ui = u - u.dot(&ab) * ab;
+    let vj_mag = (v_mag * v_mag - ui.magnitude_squared()).max(0.0).sqrt();
+    ab * vj_mag + ui
+}
+impl Boss {
+    /// Start phase `phase`, with its attacks and charges reloading
+    fn enter(&mut self, phase: usize) {
+        let next = &self.class.phases[phase];
+        self.reloads = next.attacks.iter().map(|a| ticks(a.every)).collect();
+        self.charge_reload = match next.movement {
+            Movement::Charge { every, .. } => ticks(every),
+            _ => 0,
+        };
+        self.charging = 0;
+        self.phase = phase;
+        self.phase_time = 0;
+    }
+
+    /// Whether the phase the boss is in keeps it from taking damage
+    pub fn shielded(&self) -> bool {
+        self.class.phases[self.phase].shield
+    }
+
+    pub fn tick<'a>(
+        &mut self,
+        map: &Map,
+        ids: &mut IdGen,
+        boss_bullets: &mut Vec<BossBullet>,
+        mut players: Peekable<impl Iterator<Item = &'a Player>>,
+    ) {
+        // Move on past phases that ran their time, or to the one the boss is
+        // down to
+        self.phase_time += 1;
+        while let Some(next) = self.class.phases.get(self.phase + 1) {
+            let phase = &self.class.phases[self.phase];
+            let over = match phase.duration {
+                0 => self.health <= next.health,
+                duration => self.phase_time >= ticks(duration),
+            };
+            if !over {
+                break;
+            }
+            self.enter(self.phase + 1);
+        }
+        for reload in &mut self.reloads {
+            *reload = reload.saturating_sub(1);
+        }
+
+        let mut nearest_player = *players.peek().unwrap();
+        let mut nearest_dist = f32::MAX;
+        for p in players {
+            let dist = map.distance(p.pos, self.pos);
+            if dist < nearest_dist {
+                nearest_dist = dist;
+                nearest_player = p;
+            }
+        }
+
+        // Aim from where the boss is before it moves
+        let from = self.pos;
+        let at = map.closest_copy(nearest_player.pos, from);
+        let aim = |speed: f32| intercept(from, at, nearest_player.vel, speed);
+        let phase = &self.class.phases[self.phase];
+        let chase = |vel: &mut Vector2<f32>, acceleration: f32| {
+            *vel += aim(Boss::LEAD).normalize() * acceleration;
+            *vel *= 0.9_f32;
+        };
+        match phase.movement {
+            Movement::Chase { acceleration } => chase(&mut self.vel, acceleration),
+            Movement::Charge {
+                acceleration,
+                speed,
+                every,
+                duration,
+            } => {
+                if self.charging > 0 {
+                    self.charging -= 1;
+                } else {
+                    self.charge_reload = self.charge_reload.saturating_sub(1);
+                    if self.charge_reload == 0 {
+                        let dir = (at - from).try_normalize(1.0e-6).unwrap_or_else(Vector2::x);
+                        self.vel = dir * speed;
+                        self.charging = ticks(duration);
+                        self.charge_reload = ticks(every);
+                    } else {
+                        chase(&mut self.vel, acceleration);
+                    }
+                }
+            }
+            Movement::Hold => self.vel *= 0.9_f32,
+        }
+        self.pos += self.vel;
+        self.pos = map.confine(self.pos);
+
+        for (attack, reload) in phase.attacks.iter().zip(&mut self.reloads) {
+            if *reload > 0 {
+                continue;
+            }
+            *reload = ticks(attack.every);
+            let mut fire = |pos, vel, behaviors| {
+                boss_bullets.push(BossBullet {
+                    pos,
+                    id: ids.next_id(),
+                    ttl: ticks(attack.lifetime),
+                    behaviors,
+                    age: 0,
+                    vel,
+                })
+            };
+            let ring = |n: u32, spin: f32| {
+                (0..n).map(move |i| {
+                    let angle = (spin + 360.0 * i as f32 / n as f32).to_radians();
+                    Vector2::new(angle.cos(), angle.sin()) * attack.speed
+                })
+            };
+            match attack.volley {
+                Volley::Aimed { bullets, spread } => {
+                    let vel = aim(attack.speed);
+                    if bullets == 1 {
+                        fire(self.pos, vel, attack.behaviors.clone());
+                    } else {
+                        for i in 0..bullets {
+                            let turn =
+                                (i as f32 / (bullets - 1) as f32 - 0.5) * spread.to_radians();
+                            fire(
+                                self.pos,
+                                Rotation2::new(turn) * vel,
+                                attack.behaviors.clone(),
+                            );
+                        }
+                    }
+                }
+                Volley::Flank { distance, drift } => {
+                    let vel = aim(attack.speed);
+                    let velp = Vector2::new(vel.y, -vel.x).normalize();
+                    for side in [-1.0, 1.0] {
+                        fire(
+                            self.pos + velp * distance * side,
+                            vel * drift,
+                            attack.behaviors.clone(),
+                        );
+                    }
+                }
+                Volley::Spiral { bullets, turn } => {
+                    for vel in ring(bullets, self.spin) {
+                        fire(self.pos, vel, attack.behaviors.clone());
+                    }
+                    self.spin += turn;
+                }
+                Volley::Summon { count, range, turn } => {
+                    let mut behaviors = attack.behaviors.clone();
+                    behaviors.push(Behavior::Homing { range, turn });
+                    for vel in ring(count, self.spin) {
+                        fire(self.pos, vel, behaviors.clone());
+                    }
+                }
+            }
+        }
+    }
+}
+pub struct BossBullet {
+    pub vel: Vector2<f32>,
+    pub pos: Vector2<f32>,
+    /// Ticks left before it disappears
+    pub ttl: u32,
+    pub id: usize,
+    pub behaviors: Vec<Behavior>,
+    /// Ticks since it was fired
+    pub age: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::class::Classes;
+    use crate::map::Zone;
+    use crate::world::World;
+    use crate::Input;
+
+    /// A world with the one boss in `bosses` at (800, 800), and alice at
+    /// `pos` to keep it busy
+    fn world(bosses: &str, pos: Vector2<f32>) -> World {
+        let boss = Bosses::parse(bosses).unwrap().0.remove(0);
+        let map = Map {
+            boss_zones: vec![Zone {
+                pos: Vector2::new(800.0, 800.0),
+                radius: 0.0,
+            }],
+            ..Map::default()
+        };
+        let mut world = World::new(1, map, Some(boss), false);
+        let alice = Player::new(1, pos, "alice".to_owned(), "Sniper".to_owned());
+        world.players.insert(1, alice);
+        world
+    }
+
+    #[test]
+    fn bosses_go_through_their_phases() {
+        let mut world = world(
+            r#"
+            [[boss]]
+            name = "Turret"
+
+            [[boss.phases]]
+            health = 255
+            duration = 1000
+            shield = true
+            movement = { kind = "hold" }
+
+            [[boss.phases]]
+            health = 255
+            movement = { kind = "hold" }
+
+            [[boss.phases.attacks]]
+            pattern = "spiral"
+            bullets = 8
+            turn = 10.0
+            every = 200
+            speed = 2.0
+            lifetime = 3000
+            "#,
+            // Down in a corner, out of reach of the bullets
+            Vector2::new(1500.0, 1500.0),
+        );
+        // Shielded and quiet at first, then firing rings of bullets
+        for _ in 1..ticks(1000) {
+            world.step(Vec::new());
+            assert!(world.boss.as_ref().unwrap().shielded());
+            assert!(world.boss_bullets.is_empty());
+        }
+        world.step(Vec::new());
+        assert!(!world.boss.as_ref().unwrap().shielded());
+        for _ in 0..2 * ticks(200) {
+            world.step(Vec::new());
+        }
+        assert_eq!(world.boss_bullets.len(), 16);
+    }
+
+    #[test]
+    fn bosses_shield_up_once_down_to_a_phase() {
+        let phases = |second: u8| {
+            format!(
+                r#"
+                [[boss]]
+                name = "Target"
+
+                [[boss.phases]]
+                health = 255
+                movement = {{ kind = "hold" }}
+
+                [[boss.phases]]
+                health = {}
+                shield = true
+                movement = {{ kind = "hold" }}
+                "#,
+                second
+            )
+        };
+        assert!(Bosses::parse(&phases(255)).is_err());
+        let mut world = world(&phases(200), Vector2::new(1000.0, 800.0));
+        world.classes = Classes::parse(
+            r#"
+            [[class]]
+            name = "Gunner"
+            pattern = "fan"
+            cooldown = 200
+            bullets = 1
+            speed = 12.0
+            spread = 0.0
+            lifetime = 1000
+            damage = 40
+            mana_regen = 20
+            "#,
+        )
+        .unwrap();
+        world.players.get_mut(&1).unwrap().class = "Gunner".to_owned();
+
+        // Shot down to the health of the second phase, the boss shields up
+        // and takes no more damage
+        world.step(vec![
+            (1, Input::Target(-Vector2::x())),
+            (1, Input::Click(true)),
+        ]);
+        while !world.boss.as_ref().unwrap().shielded() {
+            world.step(Vec::new());
+        }
+        let health = world.boss.as_ref().unwrap().health;
+        assert_eq!(health, 255 - 2 * 40);
+        for _ in 0..60 {
+            world.step(Vec::new());
+            assert_eq!(world.boss.as_ref().unwrap().health, health);
+        }
+    }
+}
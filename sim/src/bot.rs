@@ -0,0 +1,274 @@
+//! Computer controlled players.
+//!
+//! A `Bot` plays through the same `Input`s a client sends, so the world can't
+//! tell it apart from a human and replays of matches with bots need nothing
+//! special. Every so often, depending on its `Difficulty`, it looks at the
+//! world through `Senses`: it dodges bullets coming its way, fights the boss
+//! or the nearest player in range, and otherwise roams around or heads for a
+//! wormhole.
+use na::{Rotation2, Vector2};
+use nalgebra as na;
+use rand::prelude::*;
+use rstar::RTree;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::boss::BossBullet;
+use crate::bullet::Bullet;
+use crate::consts::ticks;
+use crate::player::Player;
+use crate::world::{Input, World};
+
+const NAMES: [&str; 12] = [
+    "Ace", "Blip", "Comet", "Dart", "Echo", "Flux", "Glint", "Halo", "Ion", "Jinx", "Kite", "Lumen",
+];
+
+#[derive(Deserialize, Serialize, Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Difficulty {
+    Easy,
+    Normal,
+    Hard,
+}
+
+impl Difficulty {
+    pub fn from_name(name: &str) -> Option<Difficulty> {
+        match name {
+            "easy" => Some(Difficulty::Easy),
+            "normal" => Some(Difficulty::Normal),
+            "hard" => Some(Difficulty::Hard),
+            _ => None,
+        }
+    }
+    /// Ticks between two looks at the world
+    fn reaction(self) -> u32 {
+        ticks(match self {
+            Difficulty::Easy => 400,
+            Difficulty::Normal => 200,
+            Difficulty::Hard => 80,
+        })
+    }
+    /// Largest angle aim is off by, in radians
+    fn aim_error(self) -> f32 {
+        match self {
+            Difficulty::Easy => 0.5,
+            Difficulty::Normal => 0.25,
+            Difficulty::Hard => 0.08,
+        }
+    }
+    /// How close bullets have to be to be dodged, easy bots don't bother
+    fn dodge_range(self) -> f32 {
+        match self {
+            Difficulty::Easy => 0.0,
+            Difficulty::Normal => 150.0,
+            Difficulty::Hard => 250.0,
+        }
+    }
+    /// Chance to head for a wormhole after spawning
+    fn curiosity(self) -> f64 {
+        match self {
+            Difficulty::Easy => 0.1,
+            Difficulty::Normal => 0.25,
+            Difficulty::Hard => 0.5,
+        }
+    }
+}
+
+/// What bots look at, built once per step for all of them
+pub struct Senses<'a> {
+    world: &'a World,
+    bullets: RTree<&'a Bullet>,
+    boss_bullets: RTree<&'a BossBullet>,
+}
+
+impl<'a> Senses<'a> {
+    pub fn new(world: &'a World) -> Senses<'a> {
+        Senses {
+            world,
+            bullets: RTree::bulk_load(world.bullets.iter().collect()),
+            boss_bullets: RTree::bulk_load(world.boss_bullets.iter().collect()),
+        }
+    }
+
+    /// Which way to go to get out of the way of bullets within `range` of
+    /// `p`, if any are coming at it
+    fn dodge(&self, p: &Player, range: f32) -> Option<Vector2<f32>> {
+        if range <= 0.0 {
+            return None;
+        }
+        let map = &self.world.map;
+        let envelopes = map.around(p.pos, Vector2::repeat(range));
+        let hostile = envelopes
+            .iter()
+            .flat_map(|envelope| self.bullets.locate_in_envelope_intersecting(envelope))
+            .filter(|b| self.world.pvp_enabled && b.owner != p.id)
+            .map(|b| (b.pos, b.vel));
+        let boss = envelopes
+            .iter()
+            .flat_map(|envelope| self.boss_bullets.locate_in_envelope_intersecting(envelope))
+            .map(|b| (b.pos, b.vel));
+        let mut away = Vector2::zeros();
+        for (pos, vel) in hostile.chain(boss) {
+            let d = map.offset(pos, p.pos);
+            let dir = match vel.try_normalize(1.0e-6) {
+                Some(dir) if d.dot(&dir) > 0.0 => dir,
+                // Not moving towards us
+                _ => continue,
+            };
+            // Step off the line the bullet flies along, harder the closer it is
+            let off = d - dir * d.dot(&dir);
+            let off = off
+                .try_normalize(1.0e-6)
+                .unwrap_or(Vector2::new(-dir.y, dir.x));
+            away += off / d.magnitude().max(1.0);
+        }
+        away.try_normalize(1.0e-6)
+    }
+}
+
+pub struct Bot {
+    pub id: usize,
+    pub difficulty: Difficulty,
+    pub name: String,
+    pub class: String,
+    rng: StdRng,
+    /// Ticks until the next look at the world
+    wait: u32,
+    alive: bool,
+    /// Heading for a wormhole
+    wander: bool,
+    /// Where to roam to when there is nothing to do
+    roam: Vector2<f32>,
+    mouse: bool,
+    split: bool,
+    join: bool,
+    escape: bool,
+}
+
+impl Bot {
+    /// A bot of one of the classes of `world`
+    pub fn new(id: usize, difficulty: Difficulty, world: &World, seed: u64) -> Bot {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let name = format!("{} [bot]", NAMES.choose(&mut rng).unwrap());
+        let names: Vec<&String> = world.classes.iter().map(|c| &c.name).collect();
+        let class = names.choose(&mut rng).unwrap().to_string();
+        Bot {
+            id,
+            difficulty,
+            name,
+            class,
+            roam: world.map.anywhere(&mut rng),
+            rng,
+            wait: 0,
+            alive: false,
+            wander: false,
+            mouse: false,
+            split: false,
+            join: false,
+            escape: false,
+        }
+    }
+
+    /// The bot was moved to another arena
+    pub fn arrived(&mut self) {
+        self.wander = false;
+        self.wait = 0;
+    }
+
+    /// The inputs of the bot for the next step
+    pub fn think(&mut self, senses: &Senses) -> Vec<Input> {
+        let mut inputs = Vec::new();
+        let p = match senses.world.players.get(&self.id) {
+            Some(p) => p,
+            None => {
+                if self.alive {
+                    // Died, take a moment before coming back
+                    self.alive = false;
+                    self.wait = ticks(3000);
+                }
+                if self.wait == 0 {
+                    self.alive = true;
+                    self.wander = self.rng.gen_bool(self.difficulty.curiosity());
+                    self.mouse = false;
+                    self.split = false;
+                    self.join = false;
+                    self.escape = false;
+                    inputs.push(Input::Spawn(self.name.clone(), self.class.clone()));
+                } else {
+                    self.wait -= 1;
+                }
+                return inputs;
+            }
+        };
+        self.alive = true;
+        if self.wait > 0 {
+            self.wait -= 1;
+            return inputs;
+        }
+        self.wait = self.difficulty.reaction();
+
+        let world = senses.world;
+        let map = &world.map;
+        let range = world.classes.get_or_first(&self.class).range() * 0.8;
+        // Everything is looked at where it is closest, across the seam of a
+        // torus
+        let enemy = match &world.boss {
+            Some(boss) if boss.health > 0 => Some(map.closest_copy(boss.pos, p.pos)),
+            _ if world.pvp_enabled => world
+                .players
+                .values()
+                .filter(|o| o.id != p.id)
+                .map(|o| map.closest_copy(o.pos, p.pos))
+                .filter(|at| (at - p.pos).magnitude() < range * 1.5)
+                .min_by(|a, b| (a - p.pos).magnitude().total_cmp(&(b - p.pos).magnitude())),
+            _ => None,
+        };
+        let wormhole = world
+            .wormholes
+            .iter()
+            .map(|w| map.closest_copy(w.pos, p.pos))
+            .filter(|_| self.wander)
+            .min_by(|a, b| (a - p.pos).magnitude().total_cmp(&(b - p.pos).magnitude()));
+        let dodge = senses.dodge(p, self.difficulty.dodge_range());
+
+        let mut mouse = false;
+        let mut split = false;
+        let target = if let Some(away) = dodge {
+            split = self.difficulty == Difficulty::Hard;
+            away * 200.0
+        } else if let Some(w) = wormhole {
+            w - p.pos
+        } else if let Some(enemy) = enemy {
+            let error = self.difficulty.aim_error();
+            let aim = Rotation2::new(self.rng.gen_range(-error..=error)) * (enemy - p.pos);
+            mouse = aim.magnitude() < range;
+            aim
+        } else {
+            if map.distance(p.pos, self.roam) < 100.0 || !map.contains(self.roam) {
+                self.roam = map.anywhere(&mut self.rng);
+            }
+            map.offset(p.pos, self.roam)
+        };
+        inputs.push(Input::Target(target));
+
+        // Boss arenas are left once the boss is beaten or when hurting
+        let escape =
+            world.escapable && (p.health < 80 || world.boss.as_ref().is_none_or(|b| b.health == 0));
+        let join = wormhole.is_some();
+        if mouse != self.mouse {
+            self.mouse = mouse;
+            inputs.push(Input::Click(mouse));
+        }
+        if split != self.split {
+            self.split = split;
+            inputs.push(Input::Split(split));
+        }
+        if join != self.join {
+            self.join = join;
+            inputs.push(Input::Join(join));
+        }
+        if escape != self.escape {
+            self.escape = escape;
+            inputs.push(Input::Escape(escape));
+        }
+        inputs
+    }
+}
@@ -0,0 +1,81 @@
+use na::Vector2;
+use nalgebra as na;
+use rstar::{RTreeObject, AABB};
+
+use crate::class::Class;
+use crate::projectile::Behavior;
+
+pub struct Bullet {
+    pub vel: Vector2<f32>,
+    pub pos: Vector2<f32>,
+    /// Ticks left before it disappears
+    pub ttl: u32,
+    /// Health it takes from what it hits
+    pub damage: u8,
+    /// How many more targets it goes through before it is used up
+    pub pierce: u32,
+    /// Speed it gives what it hits, in the direction it flies
+    pub knockback: f32,
+    /// Share of `damage` its owner is healed by on a hit
+    pub lifesteal: f32,
+    /// What it already went through, so it hits each target once
+    pub hit: Vec<Target>,
+    pub behaviors: Vec<Behavior>,
+    /// Ticks since it was fired
+    pub age: u32,
+    pub id: usize,
+    pub owner: usize,
+}
+
+/// Something a bullet can hit
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Target {
+    Boss,
+    Player(usize),
+}
+
+impl Bullet {
+    pub const RADIUS: f32 = 10.0;
+
+    /// A bullet of `class`, fired by player `owner`
+    pub fn new(
+        class: &Class,
+        id: usize,
+        owner: usize,
+        pos: Vector2<f32>,
+        vel: Vector2<f32>,
+    ) -> Bullet {
+        Bullet {
+            pos,
+            vel,
+            ttl: class.bullet_lifetime(),
+            damage: class.damage,
+            pierce: class.pierce,
+            knockback: class.knockback,
+            lifesteal: class.lifesteal,
+            hit: Vec::new(),
+            behaviors: class.behaviors.clone(),
+            age: 0,
+            id,
+            owner,
+        }
+    }
+}
+
+impl PartialEq for Bullet {
+    fn eq(&self, other: &Bullet) -> bool {
+        self.id == other.id
+    }
+}
+
+impl RTreeObject for &Bullet {
+    type Envelope = AABB<[f32; 2]>;
+
+    fn envelope(&self) -> Self::Envelope {
+        let size = Bullet::RADIUS;
+        AABB::from_corners(
+            [self.pos.x - size, self.pos.y - size],
+            [self.pos.x + size, self.pos.y + size],
+        )
+    }
+}
@@ -0,0 +1,170 @@
+//! Weapon classes, described by data instead of code.
+//!
+//! A `Classes` catalogue is read from TOML, a list of `[[class]]` tables
+//! with the fields of `Class`. `Classes::default()` is the one in
+//! `classes.toml` next to this crate, servers can load another at startup.
+//! Players refer to their class by name.
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::ability::{Ability, MAX_ABILITIES};
+use crate::consts::{ticks, TICK_MILLIS};
+use crate::projectile::Behavior;
+
+/// How the bullets of a volley are laid out
+#[derive(Deserialize, Serialize, Copy, Clone, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Pattern {
+    /// Spread over a disc of radius `spread` around the player, all flying
+    /// at the target
+    Sunflower,
+    /// Fanned out over `spread` degrees, closing in the further away the
+    /// target is
+    Fan,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Class {
+    pub name: String,
+    pub pattern: Pattern,
+    /// Milliseconds between two volleys
+    pub cooldown: u64,
+    /// Bullets in a volley
+    pub bullets: u32,
+    /// Distance bullets fly each tick
+    pub speed: f32,
+    pub spread: f32,
+    /// Milliseconds a bullet flies for
+    pub lifetime: u64,
+    /// Health a bullet takes from what it hits
+    pub damage: u8,
+    /// Targets a bullet goes through after the first one
+    #[serde(default)]
+    pub pierce: u32,
+    /// Speed a bullet gives what it hits
+    #[serde(default)]
+    pub knockback: f32,
+    /// Share of the damage a hit heals the shooter by
+    #[serde(default)]
+    pub lifesteal: f32,
+    /// Health a boosting player takes from what it rams, per unit of speed
+    /// they close in at
+    #[serde(default)]
+    pub ram: f32,
+    /// How bullets fly besides straight
+    #[serde(default)]
+    pub behaviors: Vec<Behavior>,
+    /// Milliseconds to regain one point of mana
+    pub mana_regen: u64,
+    /// What the ability slots do, in order
+    #[serde(default = "default_abilities")]
+    pub abilities: Vec<Ability>,
+}
+
+fn default_abilities() -> Vec<Ability> {
+    vec![Ability::boost()]
+}
+
+impl Class {
+    /// Ticks between two volleys
+    pub fn shot_cooldown(&self) -> u32 {
+        ticks(self.cooldown)
+    }
+    /// Ticks a bullet lives for
+    pub fn bullet_lifetime(&self) -> u32 {
+        ticks(self.lifetime)
+    }
+    /// Distance a bullet covers before it disappears
+    pub fn range(&self) -> f32 {
+        self.speed * self.bullet_lifetime() as f32
+    }
+    /// Mana regained during step `tick`
+    pub fn mana_gain(&self, tick: u64) -> u8 {
+        let regained = |tick: u64| tick * TICK_MILLIS / self.mana_regen;
+        (regained(tick) - regained(tick.saturating_sub(1))) as u8
+    }
+}
+
+/// All the classes players can pick from, in the order clients list them
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(transparent)]
+pub struct Classes(Vec<Class>);
+
+/// The layout of a class file
+#[derive(Deserialize)]
+struct ClassFile {
+    class: Vec<Class>,
+}
+
+fn invalid(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+impl Classes {
+    /// Classes from the text of a class file
+    pub fn parse(text: &str) -> io::Result<Classes> {
+        let file: ClassFile = toml::from_str(text).map_err(invalid)?;
+        let classes = Classes(file.class);
+        classes.check()?;
+        Ok(classes)
+    }
+    /// Classes from a class file
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Classes> {
+        Classes::parse(&fs::read_to_string(path)?)
+    }
+    /// Make sure a class file describes classes that can be played
+    fn check(&self) -> io::Result<()> {
+        if self.0.is_empty() {
+            return Err(invalid("no classes"));
+        }
+        // Binary snapshots send classes as a byte
+        if self.0.len() > 256 {
+            return Err(invalid("more than 256 classes"));
+        }
+        let mut names = HashSet::new();
+        for class in &self.0 {
+            if !names.insert(&class.name) {
+                return Err(invalid(format!("class {} is there twice", class.name)));
+            }
+            if class.bullets == 0 || class.speed <= 0.0 || class.mana_regen == 0 {
+                return Err(invalid(format!(
+                    "class {} needs bullets, speed and mana_regen above 0",
+                    class.name
+                )));
+            }
+            if class.abilities.is_empty() || class.abilities.len() > MAX_ABILITIES {
+                return Err(invalid(format!(
+                    "class {} needs 1 to {} abilities",
+                    class.name, MAX_ABILITIES
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Class> {
+        self.0.iter()
+    }
+    /// The class called `name`
+    pub fn get(&self, name: &str) -> Option<&Class> {
+        self.0.iter().find(|c| c.name == name)
+    }
+    /// Where the class called `name` is in the list
+    pub fn index(&self, name: &str) -> Option<usize> {
+        self.0.iter().position(|c| c.name == name)
+    }
+    /// The class called `name`, or the first one for players whose class
+    /// is not known here
+    pub fn get_or_first(&self, name: &str) -> &Class {
+        self.get(name).unwrap_or(&self.0[0])
+    }
+}
+
+impl Default for Classes {
+    fn default() -> Classes {
+        Classes::parse(include_str!("../classes.toml")).expect("classes.toml is valid")
+    }
+}
@@ -0,0 +1,11 @@
+use std::time::Duration;
+
+pub const WORLDSIZE: f32 = 1600.0;
+/// Length of one simulation step
+pub const TICK_MILLIS: u64 = 16;
+pub const TICK: Duration = Duration::from_millis(TICK_MILLIS);
+
+/// Number of ticks closest to `millis` milliseconds
+pub const fn ticks(millis: u64) -> u32 {
+    ((millis + TICK_MILLIS / 2) / TICK_MILLIS) as u32
+}
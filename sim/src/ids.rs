@@ -0,0 +1,15 @@
+//! Ids of things living in a `World`.
+//!
+//! They come from the `World` itself, so a seeded world hands out the same
+//! ones every run, and stay small so they pack into a few bytes on the wire.
+
+/// Ids local to one `World`
+#[derive(Default)]
+pub struct IdGen(usize);
+
+impl IdGen {
+    pub fn next_id(&mut self) -> usize {
+        self.0 += 1;
+        self.0
+    }
+}
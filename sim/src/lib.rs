@@ -0,0 +1,34 @@
+//! The game simulation of s3, without any networking.
+//!
+//! A `World` is one arena. Feed it the inputs of its players with `step` and
+//! it advances one `consts::TICK`, returning the `Event`s the outside world
+//! has to act on, like players dying or going through wormholes. The same
+//! seed and inputs always give the same world, which is what `replay` is
+//! built on.
+//!
+//! ```
+//! use s3_sim::map::Map;
+//! use s3_sim::{Input, World};
+//!
+//! let mut world = World::new(1, Map::default(), None, false);
+//! world.step(vec![(1, Input::Spawn("bob".to_owned(), "Sniper".to_owned()))]);
+//! for _ in 0..60 {
+//!     world.step(vec![(1, Input::Click(true))]);
+//! }
+//! assert!(!world.bullets.is_empty());
+//! ```
+pub mod ability;
+pub mod boss;
+pub mod bot;
+pub mod bullet;
+pub mod class;
+pub mod consts;
+pub mod ids;
+pub mod map;
+pub mod obstacle;
+pub mod player;
+pub mod projectile;
+pub mod replay;
+pub mod world;
+
+pub use world::{Event, Input, World};
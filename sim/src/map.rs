@@ -0,0 +1,329 @@
+//! Arena layouts.
+//!
+//! A `Map` says how big an arena is and what shape, where players spawn,
+//! where wormholes open, where the boss shows up and which obstacles are in
+//! the way. Maps are JSON files, where anything can be left out:
+//!
+//! ```json
+//! {
+//!     "size": [2000, 1200],
+//!     "geometry": "rectangle",
+//!     "spawns": [[200, 200], [1800, 1000]],
+//!     "wormholes": [[0, 600], [2000, 600]],
+//!     "boss_zones": [{ "pos": [1000, 600], "radius": 300 }],
+//!     "obstacles": [{ "pos": [1000, 300], "shape": { "circle": 80 }, "model": "meteorFull" }]
+//! }
+//! ```
+//!
+//! A map without a `size` is a `WORLDSIZE` square, like `Map::default()`,
+//! which is empty. Where a map gives no places for something, it goes
+//! anywhere in the arena, or for wormholes anywhere on its edge.
+//!
+//! A `circle` arena is the largest circle that fits in `size`. A `torus` is
+//! a `size` rectangle whose opposite edges meet, so everything that flies
+//! out one side comes back in the other and distances are measured the short
+//! way round. Players, the boss and bullets all go through the map for that,
+//! and spatial queries use `around`, which splits a box that crosses the
+//! seam into the pieces on either side.
+use na::Vector2;
+use nalgebra as na;
+use rand::prelude::*;
+use rstar::AABB;
+use serde_derive::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::consts::WORLDSIZE;
+use crate::obstacle::Obstacle;
+
+/// A disc things are placed in at random
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Zone {
+    pub pos: Vector2<f32>,
+    pub radius: f32,
+}
+
+impl Zone {
+    /// A point spread evenly over the zone
+    pub fn sample(&self, rng: &mut StdRng) -> Vector2<f32> {
+        let r = self.radius * rng.gen::<f32>().sqrt();
+        let angle = rng.gen_range(0.0..std::f32::consts::TAU);
+        self.pos + Vector2::new(angle.cos(), angle.sin()) * r
+    }
+}
+
+/// The shape of an arena and what its edges do
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Geometry {
+    /// Walled in at the edges of `size`
+    Rectangle,
+    /// Walled in by the largest circle that fits in `size`
+    Circle,
+    /// Opposite edges of `size` meet
+    Torus,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct Map {
+    /// Width and height of the arena
+    pub size: Vector2<f32>,
+    pub geometry: Geometry,
+    /// Where players spawn
+    pub spawns: Vec<Vector2<f32>>,
+    /// Where wormholes open, in the order they are opened
+    pub wormholes: Vec<Vector2<f32>>,
+    /// Where the boss shows up
+    pub boss_zones: Vec<Zone>,
+    pub obstacles: Vec<Obstacle>,
+}
+
+impl Default for Map {
+    fn default() -> Map {
+        Map {
+            size: Vector2::new(WORLDSIZE, WORLDSIZE),
+            geometry: Geometry::Rectangle,
+            spawns: Vec::new(),
+            wormholes: Vec::new(),
+            boss_zones: Vec::new(),
+            obstacles: Vec::new(),
+        }
+    }
+}
+
+fn invalid(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+impl Map {
+    /// A map from a JSON file
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Map> {
+        let map: Map = serde_json::from_str(&fs::read_to_string(path)?).map_err(invalid)?;
+        map.check()?;
+        Ok(map)
+    }
+
+    /// Make sure everything the map places is in the arena
+    fn check(&self) -> io::Result<()> {
+        if self.size.x <= 0.0 || self.size.y <= 0.0 {
+            return Err(invalid("the arena needs a size above 0"));
+        }
+        let zones = self.boss_zones.iter().map(|z| z.pos);
+        for pos in self
+            .spawns
+            .iter()
+            .chain(&self.wormholes)
+            .copied()
+            .chain(zones)
+        {
+            if !self.contains(pos) {
+                return Err(invalid(format!(
+                    "({}, {}) is outside the arena",
+                    pos.x, pos.y
+                )));
+            }
+        }
+        for o in &self.obstacles {
+            o.check()?;
+        }
+        Ok(())
+    }
+
+    /// The middle and radius of a circle arena
+    fn circle(&self) -> (Vector2<f32>, f32) {
+        (self.size / 2.0, self.size.min() / 2.0)
+    }
+
+    /// Whether `pos` is in the arena
+    pub fn contains(&self, pos: Vector2<f32>) -> bool {
+        match self.geometry {
+            Geometry::Rectangle | Geometry::Torus => {
+                (0.0..=self.size.x).contains(&pos.x) && (0.0..=self.size.y).contains(&pos.y)
+            }
+            Geometry::Circle => {
+                let (center, radius) = self.circle();
+                (pos - center).magnitude() <= radius
+            }
+        }
+    }
+
+    /// Where something that went to `pos` ends up: the point in the arena
+    /// closest to it, or on a torus where it wraps around to
+    pub fn confine(&self, pos: Vector2<f32>) -> Vector2<f32> {
+        match self.geometry {
+            Geometry::Rectangle => {
+                Vector2::new(pos.x.clamp(0.0, self.size.x), pos.y.clamp(0.0, self.size.y))
+            }
+            Geometry::Circle => {
+                let (center, radius) = self.circle();
+                let d = pos - center;
+                if d.magnitude() <= radius {
+                    pos
+                } else {
+                    center + d.normalize() * radius
+                }
+            }
+            Geometry::Torus => self.wrap(pos),
+        }
+    }
+
+    /// Where `pos` wraps around to on a torus, other arenas don't wrap
+    pub fn wrap(&self, pos: Vector2<f32>) -> Vector2<f32> {
+        match self.geometry {
+            Geometry::Torus => pos.zip_map(&self.size, f32::rem_euclid),
+            Geometry::Rectangle | Geometry::Circle => pos,
+        }
+    }
+
+    /// Bounce something that went to `pos` at `vel` off the walls it went
+    /// through
+    pub fn reflect(&self, pos: &mut Vector2<f32>, vel: &mut Vector2<f32>) {
+        match self.geometry {
+            Geometry::Rectangle => {
+                for axis in 0..2 {
+                    if pos[axis] < 0.0 {
+                        pos[axis] = -pos[axis];
+                        vel[axis] = -vel[axis];
+                    } else if pos[axis] > self.size[axis] {
+                        pos[axis] = 2.0 * self.size[axis] - pos[axis];
+                        vel[axis] = -vel[axis];
+                    }
+                }
+            }
+            Geometry::Circle => {
+                let (center, radius) = self.circle();
+                let d = *pos - center;
+                let dist = d.magnitude();
+                if dist > radius {
+                    let normal = d / dist;
+                    *pos = center + normal * (2.0 * radius - dist);
+                    *vel -= normal * 2.0 * vel.dot(&normal);
+                }
+            }
+            Geometry::Torus => (),
+        }
+    }
+
+    /// The copy of `pos` closest to `to`, which on a torus can be one across
+    /// the seam, outside the arena
+    pub fn closest_copy(&self, pos: Vector2<f32>, to: Vector2<f32>) -> Vector2<f32> {
+        match self.geometry {
+            Geometry::Torus => {
+                pos + (to - pos).zip_map(&self.size, |d, size| (d / size).round() * size)
+            }
+            Geometry::Rectangle | Geometry::Circle => pos,
+        }
+    }
+
+    /// The shortest way from `from` to `to`
+    pub fn offset(&self, from: Vector2<f32>, to: Vector2<f32>) -> Vector2<f32> {
+        self.closest_copy(to, from) - from
+    }
+
+    /// How far apart `a` and `b` are
+    pub fn distance(&self, a: Vector2<f32>, b: Vector2<f32>) -> f32 {
+        self.offset(a, b).magnitude()
+    }
+
+    /// The boxes covering everything within `half` of `center` along each
+    /// axis. That is one box, except on a torus where the box crosses the
+    /// seam and the parts beyond it are also looked for on the other side.
+    pub fn around(&self, center: Vector2<f32>, half: Vector2<f32>) -> Vec<AABB<[f32; 2]>> {
+        let mut boxes = vec![(center - half, center + half)];
+        if self.geometry == Geometry::Torus {
+            for axis in 0..2 {
+                let (lower, upper) = boxes[0];
+                let shift = if lower[axis] < 0.0 && upper[axis] <= self.size[axis] {
+                    self.size[axis]
+                } else if upper[axis] > self.size[axis] && lower[axis] >= 0.0 {
+                    -self.size[axis]
+                } else {
+                    continue;
+                };
+                let mut by = Vector2::zeros();
+                by[axis] = shift;
+                let shifted: Vec<_> = boxes.iter().map(|&(l, u)| (l + by, u + by)).collect();
+                boxes.extend(shifted);
+            }
+        }
+        boxes
+            .into_iter()
+            .map(|(l, u)| AABB::from_corners([l.x, l.y], [u.x, u.y]))
+            .collect()
+    }
+
+    /// Anywhere in the arena
+    pub fn anywhere(&self, rng: &mut StdRng) -> Vector2<f32> {
+        match self.geometry {
+            Geometry::Rectangle | Geometry::Torus => {
+                let x = rng.gen_range(0.0..self.size.x);
+                let y = rng.gen_range(0.0..self.size.y);
+                Vector2::new(x, y)
+            }
+            Geometry::Circle => {
+                let (pos, radius) = self.circle();
+                Zone { pos, radius }.sample(rng)
+            }
+        }
+    }
+
+    /// How far `pos` is from the nearest wall, a torus has none
+    pub fn wall_distance(&self, pos: Vector2<f32>) -> f32 {
+        match self.geometry {
+            Geometry::Rectangle => pos
+                .x
+                .min(pos.y)
+                .min(self.size.x - pos.x)
+                .min(self.size.y - pos.y),
+            Geometry::Circle => {
+                let (center, radius) = self.circle();
+                radius - (pos - center).magnitude()
+            }
+            Geometry::Torus => f32::INFINITY,
+        }
+    }
+
+    /// Places a player could spawn, in random order: every spawn point of the
+    /// map, or `count` places anywhere when it has none
+    pub fn spawn_candidates(&self, count: usize, rng: &mut StdRng) -> Vec<Vector2<f32>> {
+        if self.spawns.is_empty() {
+            return (0..count).map(|_| self.anywhere(rng)).collect();
+        }
+        let mut spawns = self.spawns.clone();
+        spawns.shuffle(rng);
+        spawns
+    }
+
+    /// Where the boss shows up
+    pub fn boss_spawn(&self, rng: &mut StdRng) -> Vector2<f32> {
+        match self.boss_zones.choose(rng) {
+            Some(zone) => self.confine(zone.sample(rng)),
+            None => self.anywhere(rng),
+        }
+    }
+
+    /// Where the wormhole opened after `opened` others goes
+    pub fn wormhole(&self, opened: usize, rng: &mut StdRng) -> Vector2<f32> {
+        if !self.wormholes.is_empty() {
+            return self.wormholes[opened % self.wormholes.len()];
+        }
+        if self.geometry == Geometry::Circle {
+            let (center, radius) = self.circle();
+            let angle = rng.gen_range(0.0..std::f32::consts::TAU);
+            return center + Vector2::new(angle.cos(), angle.sin()) * radius;
+        }
+        // On a random edge
+        let far = rng.gen::<bool>();
+        let vertical = rng.gen::<bool>();
+        let along = if vertical { self.size.y } else { self.size.x };
+        let pos = rng.gen_range(0.0..along);
+        let (width, height) = (self.size.x, self.size.y);
+        if vertical {
+            Vector2::new(if far { width } else { 0.0 }, pos)
+        } else {
+            Vector2::new(pos, if far { height } else { 0.0 })
+        }
+    }
+}
@@ -0,0 +1,224 @@
+//! Rocks and meteors that players can't fly through and bullets can't cross.
+//!
+//! An obstacle is a circle or a convex polygon around `pos`, with the name of
+//! the model clients draw it as. Layouts are generated from a seed, or read
+//! from a JSON file holding a list of obstacles:
+//!
+//! ```json
+//! [
+//!     { "pos": [400, 600], "shape": { "circle": 60 }, "model": "meteorFull" },
+//!     { "pos": [900, 800], "shape": { "polygon": [[-50, -40], [60, -30], [0, 70]] }, "model": "rocks" }
+//! ]
+//! ```
+use na::Vector2;
+use nalgebra as na;
+use rand::prelude::*;
+use rstar::{RTreeObject, AABB};
+use serde_derive::{Deserialize, Serialize};
+use std::f32::consts::TAU;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Shape {
+    /// A circle of this radius
+    Circle(f32),
+    /// A convex polygon with these corners, relative to the position
+    Polygon(Vec<Vector2<f32>>),
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Obstacle {
+    pub pos: Vector2<f32>,
+    pub shape: Shape,
+    /// What clients draw it as
+    #[serde(default)]
+    pub model: String,
+}
+
+impl RTreeObject for Obstacle {
+    type Envelope = AABB<[f32; 2]>;
+
+    fn envelope(&self) -> Self::Envelope {
+        match &self.shape {
+            Shape::Circle(r) => AABB::from_corners(
+                [self.pos.x - r, self.pos.y - r],
+                [self.pos.x + r, self.pos.y + r],
+            ),
+            Shape::Polygon(points) => AABB::from_points(
+                points
+                    .iter()
+                    .map(|p| [self.pos.x + p.x, self.pos.y + p.y])
+                    .collect::<Vec<_>>()
+                    .iter(),
+            ),
+        }
+    }
+}
+
+/// Models clients have for round and for jagged obstacles
+const ROUND_MODELS: [&str; 2] = ["meteorFull", "meteorFullRound"];
+const JAGGED_MODELS: [&str; 4] = ["rocks", "rocksOre", "rocksTall", "rocksTallOre"];
+
+fn invalid(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+impl Obstacle {
+    /// How far a circle at `at` with `radius` has to move to stop overlapping
+    /// the obstacle, if it does
+    pub fn push_out(&self, at: Vector2<f32>, radius: f32) -> Option<Vector2<f32>> {
+        match &self.shape {
+            Shape::Circle(r) => {
+                let d = at - self.pos;
+                let dist = d.magnitude();
+                if dist >= r + radius {
+                    return None;
+                }
+                Some(d.try_normalize(1.0e-6).unwrap_or_else(Vector2::x) * (r + radius - dist))
+            }
+            Shape::Polygon(points) => {
+                // The inside is left of every edge going counterclockwise
+                let area: f32 = (0..points.len())
+                    .map(|i| points[i].perp(&points[(i + 1) % points.len()]))
+                    .sum();
+                let mut inside = true;
+                let mut nearest = (f32::MAX, at);
+                for i in 0..points.len() {
+                    let a = self.pos + points[i];
+                    let edge = points[(i + 1) % points.len()] - points[i];
+                    if edge.perp(&(at - a)) * area.signum() < 0.0 {
+                        inside = false;
+                    }
+                    let t = ((at - a).dot(&edge) / edge.magnitude_squared()).clamp(0.0, 1.0);
+                    let closest = a + edge * t;
+                    let dist = (at - closest).magnitude();
+                    if dist < nearest.0 {
+                        nearest = (dist, closest);
+                    }
+                }
+                let (dist, closest) = nearest;
+                if inside {
+                    let out = (closest - at)
+                        .try_normalize(1.0e-6)
+                        .unwrap_or_else(Vector2::x);
+                    Some(out * (dist + radius))
+                } else if dist < radius {
+                    Some((at - closest).normalize() * (radius - dist))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    /// A layout of `count` obstacles in an arena of `size`, the same for the
+    /// same `seed`. The edges of the arena, where wormholes open, are kept
+    /// clear.
+    pub fn generate(seed: u64, count: usize, size: Vector2<f32>) -> Vec<Obstacle> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let margin = 200.0;
+        (0..count)
+            .map(|_| {
+                let pos = Vector2::new(
+                    rng.gen_range(margin..(size.x - margin).max(margin + 1.0)),
+                    rng.gen_range(margin..(size.y - margin).max(margin + 1.0)),
+                );
+                if rng.gen() {
+                    Obstacle {
+                        pos,
+                        shape: Shape::Circle(rng.gen_range(30.0..80.0)),
+                        model: ROUND_MODELS.choose(&mut rng).unwrap().to_string(),
+                    }
+                } else {
+                    // Corners on a circle are always convex
+                    let radius = rng.gen_range(50.0..110.0);
+                    let mut angles: Vec<f32> = (0..rng.gen_range(5..9))
+                        .map(|_| rng.gen_range(0.0..TAU))
+                        .collect();
+                    angles.sort_by(f32::total_cmp);
+                    angles.dedup();
+                    Obstacle {
+                        pos,
+                        shape: Shape::Polygon(
+                            angles
+                                .into_iter()
+                                .map(|a| Vector2::new(a.cos(), a.sin()) * radius)
+                                .collect(),
+                        ),
+                        model: JAGGED_MODELS.choose(&mut rng).unwrap().to_string(),
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Obstacles from a JSON file
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Vec<Obstacle>> {
+        let obstacles: Vec<Obstacle> =
+            serde_json::from_str(&fs::read_to_string(path)?).map_err(invalid)?;
+        for o in &obstacles {
+            o.check()?;
+        }
+        Ok(obstacles)
+    }
+
+    /// Make sure the obstacle has a shape collisions work with
+    pub fn check(&self) -> io::Result<()> {
+        match &self.shape {
+            Shape::Circle(r) if *r <= 0.0 => Err(invalid("circles need a radius above 0")),
+            Shape::Circle(_) => Ok(()),
+            Shape::Polygon(points) => {
+                if points.len() < 3 {
+                    return Err(invalid("polygons need at least 3 corners"));
+                }
+                // Turning the same way at every corner, and once around in
+                // total, which stars winding around twice don't
+                let turns: Vec<f32> = (0..points.len())
+                    .map(|i| {
+                        let [a, b, c] = [0, 1, 2].map(|k| points[(i + k) % points.len()]);
+                        let (from, to) = (b - a, c - b);
+                        from.perp(&to).atan2(from.dot(&to))
+                    })
+                    .collect();
+                let same_way = turns.iter().all(|&t| t > 0.0) || turns.iter().all(|&t| t < 0.0);
+                let total: f32 = turns.iter().sum();
+                if same_way && (total.abs() - TAU).abs() < 1.0e-3 {
+                    Ok(())
+                } else {
+                    Err(invalid(format!(
+                        "polygon at ({}, {}) is not convex",
+                        self.pos.x, self.pos.y
+                    )))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn obstacles_must_be_convex() {
+        // The corners of a pentagon in order are convex, every second one is a
+        // star that turns the same way at each corner but winds around twice
+        let polygon = |step: usize| Obstacle {
+            pos: Vector2::new(800.0, 800.0),
+            shape: Shape::Polygon(
+                (0..5)
+                    .map(|i| {
+                        let angle = (i * step) as f32 * TAU / 5.0;
+                        Vector2::new(angle.cos(), angle.sin()) * 50.0
+                    })
+                    .collect(),
+            ),
+            model: String::new(),
+        };
+        assert!(polygon(1).check().is_ok());
+        assert!(polygon(2).check().is_err());
+    }
+}
@@ -0,0 +1,211 @@
+use crate::ability::{Effect, Slot, MAX_ABILITIES};
+use crate::bullet::Bullet;
+use crate::class::{Class, Pattern};
+use crate::consts::ticks;
+use crate::ids::IdGen;
+use crate::map::Map;
+use na::Vector2;
+use nalgebra as na;
+use rand::prelude::*;
+use rstar::{RTreeObject, AABB};
+use serde_derive::{Deserialize, Serialize};
+use std::f32::consts::PI;
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Player {
+    pub id: usize,
+    pub vel: Vector2<f32>,
+    pub pos: Vector2<f32>,
+    pub target: Vector2<f32>,
+    pub health: u8,
+    pub mana: u8,
+    pub mouse: bool,
+    pub join: bool,
+    /// Ticks until the next volley
+    pub shot_cooldown: u32,
+    /// One for each ability of the class
+    #[serde(default)]
+    pub abilities: Vec<Slot>,
+    /// Ticks left of the protection fresh spawns get
+    #[serde(default)]
+    pub invulnerable: u32,
+    /// Ticks escape has been held for
+    pub escape_time: Option<u32>,
+    /// Name of the player's class
+    pub class: String,
+    pub name: String,
+}
+
+impl RTreeObject for &Player {
+    type Envelope = AABB<[f32; 2]>;
+
+    fn envelope(&self) -> Self::Envelope {
+        let size = Player::RADIUS;
+        AABB::from_corners(
+            [self.pos.x - size, self.pos.y - size],
+            [self.pos.x + size, self.pos.y + size],
+        )
+    }
+}
+
+fn sunflower(n: u32) -> impl Iterator<Item = Vector2<f32>> {
+    let golden = PI * (3.0 - (5.0f32).sqrt());
+    (0..n).map(move |k| {
+        let r = (k as f32).sqrt() / (n as f32).sqrt();
+        let theta = (k as f32) * golden;
+        Vector2::new(r * theta.cos(), r * theta.sin())
+    })
+}
+
+impl Player {
+    pub const RADIUS: f32 = 35.0;
+    /// How hard players are to push around, see `Boss::MASS`
+    pub const MASS: f32 = 1.0;
+    /// How long players can't be hurt after spawning, unless they shoot
+    pub const INVULNERABLE: u32 = ticks(2000);
+    pub fn new(id: usize, pos: Vector2<f32>, name: String, class: String) -> Player {
+        Player {
+            id,
+            vel: Vector2::new(0.0, 0.0),
+            pos,
+            shot_cooldown: 0,
+            abilities: Vec::new(),
+            invulnerable: Player::INVULNERABLE,
+            escape_time: None,
+            target: Vector2::new(0.0, 0.0),
+            health: 255,
+            mana: 255,
+            name,
+            class,
+            mouse: false,
+            join: false,
+        }
+    }
+
+    /// Hold or let go of the key of ability `slot`
+    pub fn hold(&mut self, slot: usize, held: bool) {
+        if slot < MAX_ABILITIES {
+            if self.abilities.len() <= slot {
+                self.abilities.resize(slot + 1, Slot::default());
+            }
+            self.abilities[slot].held = held;
+        }
+    }
+
+    /// The effects of `class` that are active right now
+    fn active<'a>(&'a self, class: &'a Class) -> impl Iterator<Item = Effect> + 'a {
+        self.abilities
+            .iter()
+            .zip(&class.abilities)
+            .filter(|(slot, _)| slot.active > 0)
+            .map(|(_, ability)| ability.effect)
+    }
+
+    /// Whether a shield or spawn protection keeps the player from taking
+    /// damage
+    pub fn shielded(&self, class: &Class) -> bool {
+        self.invulnerable > 0
+            || self
+                .active(class)
+                .any(|e| matches!(e, Effect::Shield { .. }))
+    }
+
+    /// How many times as fast boosts make the player fly
+    pub fn speed(&self, class: &Class) -> f32 {
+        self.active(class)
+            .filter_map(|e| match e {
+                Effect::Boost { speed, .. } => Some(speed),
+                _ => None,
+            })
+            .fold(1.0, f32::max)
+    }
+
+    /// Take `damage`, unless shielded
+    pub fn hurt(&mut self, class: &Class, damage: u8) {
+        if !self.shielded(class) {
+            self.health = self.health.saturating_sub(damage);
+        }
+    }
+
+    /// Cast the held abilities that are ready, returns the effects cast
+    fn cast(&mut self, class: &Class) -> Vec<Effect> {
+        self.abilities
+            .resize(class.abilities.len(), Slot::default());
+        let mut cast = Vec::new();
+        for (slot, ability) in self.abilities.iter_mut().zip(&class.abilities) {
+            if slot.held && slot.cooldown == 0 && self.mana > ability.cost {
+                slot.cooldown = ability.recast();
+                slot.active = ability.duration();
+                self.mana -= ability.cost;
+                cast.push(ability.effect);
+            }
+        }
+        cast
+    }
+
+    /// Step the player, returns the abilities it cast for the world to act
+    /// on
+    pub fn tick(
+        &mut self,
+        class: &Class,
+        map: &Map,
+        rng: &mut StdRng,
+        ids: &mut IdGen,
+        bullets: &mut Vec<Bullet>,
+    ) -> Vec<Effect> {
+        self.shot_cooldown = self.shot_cooldown.saturating_sub(1);
+        self.invulnerable = self.invulnerable.saturating_sub(1);
+        for slot in &mut self.abilities {
+            slot.cooldown = slot.cooldown.saturating_sub(1);
+            slot.active = slot.active.saturating_sub(1);
+        }
+
+        let acc = self.target.try_normalize(1.0e-6).unwrap_or_else(Vector2::y);
+        let cast = self.cast(class);
+        for effect in &cast {
+            if let Effect::Blink { distance } = *effect {
+                self.pos += acc * distance;
+            }
+        }
+        self.vel += 0.6 * acc;
+        self.vel *= 0.9_f32;
+        self.pos += self.vel * self.speed(class);
+        self.pos = map.confine(self.pos);
+
+        if self.mouse && self.shot_cooldown == 0 {
+            let owner = self.id;
+            let mut bullet = |pos, vel| Bullet::new(class, ids.next_id(), owner, pos, vel);
+            match class.pattern {
+                Pattern::Sunflower => {
+                    let btarget = self.pos + acc * self.target.magnitude().max(100.0);
+                    for pos in sunflower(class.bullets) {
+                        let bpos = self.pos + class.spread * pos;
+                        let rvec = Vector2::new(rng.gen_range(-8.0..8.0), rng.gen_range(-8.0..8.0));
+                        bullets.push(bullet(
+                            bpos,
+                            (btarget - bpos + rvec).normalize() * class.speed,
+                        ));
+                    }
+                }
+                Pattern::Fan => {
+                    let wide = self.target.magnitude().clamp(100.0, 600.0);
+                    let half = class.spread.to_radians() / 2.0;
+                    let side = ((class.bullets - 1) as f32 / 2.0).max(1.0);
+                    for i in 0..class.bullets {
+                        let angle = self.target.y.atan2(self.target.x)
+                            + ((i as f32 - (class.bullets - 1) as f32 / 2.0) / side) * half;
+                        let circle = Vector2::new(angle.cos(), angle.sin());
+                        let vel = (acc * (wide - 100.0) + circle * (600.0 - wide) / 20.0)
+                            .normalize()
+                            * class.speed;
+                        bullets.push(bullet(self.pos + circle * 50.0, vel));
+                    }
+                }
+            }
+
+            self.shot_cooldown = class.shot_cooldown();
+            self.invulnerable = 0;
+        }
+        cast
+    }
+}
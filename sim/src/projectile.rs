@@ -0,0 +1,232 @@
+//! How bullets fly besides in a straight line.
+//!
+//! Bullets of players and bosses both carry a list of `Behavior`s. Homing and
+//! bouncing change how a bullet moves and are applied by `fly`. Detonations
+//! and slowing fields act on what is around a bullet, so the `World` applies
+//! those once everything has moved.
+use na::{Rotation2, Vector2};
+use nalgebra as na;
+use rstar::RTree;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::consts::ticks;
+use crate::map::Map;
+use crate::player::Player;
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Behavior {
+    /// Turn towards the nearest enemy within `range`, by up to `turn`
+    /// degrees a tick
+    Homing { range: f32, turn: f32 },
+    /// Bounce off the walls of the arena instead of flying out
+    Bounce,
+    /// Explode `fuse` milliseconds after being fired, taking `damage` from
+    /// every enemy within `radius`
+    Detonate { fuse: u64, radius: f32, damage: u8 },
+    /// Slow enemies within `radius` down to `factor` of their speed
+    Slow { radius: f32, factor: f32 },
+}
+
+/// Whether a bullet with `behaviors` that flew for `age` ticks explodes now,
+/// and how
+pub fn detonation(behaviors: &[Behavior], age: u32) -> Option<(f32, u8)> {
+    behaviors.iter().find_map(|b| match *b {
+        Behavior::Detonate {
+            fuse,
+            radius,
+            damage,
+        } if age >= ticks(fuse) => Some((radius, damage)),
+        _ => None,
+    })
+}
+
+/// The slowing fields of a bullet with `behaviors`
+pub fn fields(behaviors: &[Behavior]) -> impl Iterator<Item = (f32, f32)> + '_ {
+    behaviors.iter().filter_map(|b| match *b {
+        Behavior::Slow { radius, factor } => Some((radius, factor)),
+        _ => None,
+    })
+}
+
+/// Players in `players` within `radius` of `pos` on `map`, but not `except`
+pub fn players_within(
+    players: &RTree<&Player>,
+    map: &Map,
+    pos: Vector2<f32>,
+    radius: f32,
+    except: Option<usize>,
+) -> Vec<usize> {
+    let reach = radius + Player::RADIUS;
+    map.around(pos, Vector2::repeat(reach))
+        .iter()
+        .flat_map(|envelope| players.locate_in_envelope_intersecting(envelope))
+        .filter(|p| Some(p.id) != except && map.distance(pos, p.pos) <= reach)
+        .map(|p| p.id)
+        .collect()
+}
+
+/// Where the nearest of `players` within `range` of `pos` on `map` is,
+/// leaving out `except`. On a torus that can be across the seam, so it is
+/// the copy closest to `pos`.
+pub fn nearest_player(
+    players: &RTree<&Player>,
+    map: &Map,
+    pos: Vector2<f32>,
+    range: f32,
+    except: Option<usize>,
+) -> Option<Vector2<f32>> {
+    map.around(pos, Vector2::repeat(range))
+        .iter()
+        .flat_map(|envelope| players.locate_in_envelope_intersecting(envelope))
+        .filter(|p| Some(p.id) != except)
+        .map(|p| map.closest_copy(p.pos, pos))
+        .filter(|&at| (at - pos).magnitude() <= range)
+        .min_by(|a, b| (a - pos).magnitude().total_cmp(&(b - pos).magnitude()))
+}
+
+/// Move a bullet one tick, bouncing it off the walls of `map` or wrapping it
+/// around. `target` finds the nearest enemy within a range of a position, for
+/// homing.
+pub fn fly(
+    pos: &mut Vector2<f32>,
+    vel: &mut Vector2<f32>,
+    behaviors: &[Behavior],
+    map: &Map,
+    target: impl Fn(Vector2<f32>, f32) -> Option<Vector2<f32>>,
+) {
+    for b in behaviors {
+        if let Behavior::Homing { range, turn } = *b {
+            if let Some(at) = target(*pos, range) {
+                let want = at - *pos;
+                let angle = vel.perp(&want).atan2(vel.dot(&want));
+                let max = turn.to_radians();
+                *vel = Rotation2::new(angle.clamp(-max, max)) * *vel;
+            }
+        }
+    }
+    *pos += *vel;
+    if behaviors.contains(&Behavior::Bounce) {
+        map.reflect(pos, vel);
+    }
+    *pos = map.wrap(*pos);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::class::Classes;
+    use crate::world::World;
+    use crate::Input;
+
+    /// A world with `class` parsed, where alice (1) is at `pos` aiming at
+    /// `target`
+    fn world(class: &str, pos: Vector2<f32>, target: Vector2<f32>) -> World {
+        let mut world = World::new(1, Map::default(), None, false);
+        world.classes = Classes::parse(class).unwrap();
+        let name = world.classes.get_or_first("").name.clone();
+        let mut alice = Player::new(1, pos, "alice".to_owned(), name);
+        alice.invulnerable = 0;
+        alice.target = target;
+        world.players.insert(1, alice);
+        world
+    }
+
+    const GRENADIER: &str = r#"
+        [[class]]
+        name = "Grenadier"
+        pattern = "fan"
+        cooldown = 5000
+        bullets = 1
+        speed = 1.0
+        spread = 0.0
+        lifetime = 5000
+        damage = 20
+        behaviors = [{ detonate = { fuse = 320, radius = 80.0, damage = 30 } }]
+        mana_regen = 20
+        "#;
+
+    /// Alice fires a single bullet
+    fn fire(world: &mut World) {
+        world.step(vec![(1, Input::Click(true))]);
+        world.step(vec![(1, Input::Click(false))]);
+        assert_eq!(world.bullets.len(), 1);
+    }
+
+    #[test]
+    fn detonating_bullets_go_off_after_their_fuse() {
+        let mut world = world(GRENADIER, Vector2::new(800.0, 100.0), Vector2::x());
+        // Bob keeps to the top wall, well clear of the bullet's path but not
+        // of the blast
+        let mut bob = Player::new(
+            2,
+            Vector2::new(860.0, 0.0),
+            "bob".to_owned(),
+            "Grenadier".to_owned(),
+        );
+        bob.invulnerable = 0;
+        bob.target = -Vector2::y();
+        world.players.insert(2, bob);
+
+        fire(&mut world);
+        let mut flown = 2;
+        while !world.bullets.is_empty() {
+            assert_eq!(world.players[&2].health, 255);
+            world.step(Vec::new());
+            flown += 1;
+        }
+        // 20 ticks of fuse, far from the 5 seconds the bullet would fly for
+        assert_eq!(flown, ticks(320));
+        assert_eq!(world.players[&2].health, 255 - 30);
+    }
+
+    #[test]
+    fn detonations_are_not_direct_hits_too() {
+        let mut world = world(GRENADIER, Vector2::new(800.0, 800.0), Vector2::x());
+        fire(&mut world);
+        while world.bullets[0].age + 1 < ticks(320) {
+            world.step(Vec::new());
+        }
+        // Bob is right where the bullet goes off
+        let at = world.bullets[0].pos;
+        let mut bob = Player::new(2, at, "bob".to_owned(), "Grenadier".to_owned());
+        bob.invulnerable = 0;
+        world.players.insert(2, bob);
+        world.step(Vec::new());
+        assert!(world.bullets.is_empty());
+        assert_eq!(world.players[&2].health, 255 - 30);
+    }
+
+    #[test]
+    fn bouncing_bullets_turn_back_at_the_walls() {
+        let mut world = world(
+            r#"
+            [[class]]
+            name = "Ricochet"
+            pattern = "fan"
+            cooldown = 5000
+            bullets = 1
+            speed = 10.0
+            spread = 0.0
+            lifetime = 3000
+            damage = 20
+            behaviors = ["bounce"]
+            mana_regen = 20
+            "#,
+            Vector2::new(1400.0, 800.0),
+            Vector2::x(),
+        );
+        fire(&mut world);
+        let width = world.map.size.x;
+        while world.bullets[0].vel.x > 0.0 {
+            world.step(Vec::new());
+            assert!((0.0..=width).contains(&world.bullets[0].pos.x));
+        }
+        // Back the way it came, with all of its speed
+        assert_eq!(world.bullets[0].vel, Vector2::new(-10.0, 0.0));
+        for _ in 0..100 {
+            world.step(Vec::new());
+            assert!(world.bullets[0].pos.x < width - 10.0);
+        }
+    }
+}
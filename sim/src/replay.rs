@@ -0,0 +1,306 @@
+//! Match recordings.
+//!
+//! A replay file is JSON lines: a `Header` with everything `World::new` needs,
+//! then one `Entry` per thing that changed the world from the outside, tagged
+//! with the tick it happened after. Since a `World` is deterministic, applying
+//! the entries at the same ticks reproduces the match exactly.
+use crate::boss::{BossClass, Bosses};
+use crate::class::Classes;
+use crate::map::Map;
+use crate::player::Player;
+use crate::world::{Event, Input, World};
+use serde::de::{Deserializer, Error};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+#[derive(Serialize, Deserialize)]
+pub struct Header {
+    pub seed: u64,
+    #[serde(deserialize_with = "saved_boss")]
+    pub boss: Option<BossClass>,
+    pub escapable: bool,
+    /// Classes of the match, older replays used the built-in ones
+    #[serde(default)]
+    pub classes: Classes,
+    /// Layout of the arena, older replays were in the default one
+    #[serde(default)]
+    pub map: Map,
+}
+
+/// Older replays have the index of a built-in boss instead of the boss
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SavedBoss {
+    BuiltIn(usize),
+    Class(BossClass),
+}
+
+fn saved_boss<'de, D: Deserializer<'de>>(d: D) -> Result<Option<BossClass>, D::Error> {
+    match <Option<SavedBoss> as serde::Deserialize>::deserialize(d)? {
+        Some(SavedBoss::BuiltIn(i)) => match Bosses::default().iter().nth(i) {
+            Some(boss) => Ok(Some(boss.clone())),
+            None => Err(D::Error::custom(format!("no built-in boss {}", i))),
+        },
+        Some(SavedBoss::Class(boss)) => Ok(Some(boss)),
+        None => Ok(None),
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub enum Record {
+    /// An input from the player with this id
+    Input(usize, Input),
+    /// A player came in from another arena
+    Arrive(Player),
+    /// The player with this id disconnected
+    Leave(usize),
+    /// The player with this id was taken out for being idle
+    Kick(usize),
+    /// The round trip of this player's client was measured, in ticks
+    Latency(usize, u32),
+    /// A wormhole of this color was opened
+    Wormhole(u8),
+    /// Recording stopped
+    End,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Entry {
+    pub tick: u64,
+    pub record: Record,
+}
+
+impl Header {
+    pub fn world(&self) -> World {
+        let mut world = World::new(
+            self.seed,
+            self.map.clone(),
+            self.boss.clone(),
+            self.escapable,
+        );
+        world.classes = self.classes.clone();
+        world
+    }
+}
+
+impl Record {
+    /// The player this is about, if any
+    pub fn player(&self) -> Option<usize> {
+        match self {
+            Record::Input(id, _)
+            | Record::Leave(id)
+            | Record::Kick(id)
+            | Record::Latency(id, _) => Some(*id),
+            Record::Arrive(p) => Some(p.id),
+            Record::Wormhole(_) | Record::End => None,
+        }
+    }
+    pub fn apply(self, world: &mut World) {
+        match self {
+            Record::Input(id, m) => world.input(id, m),
+            Record::Arrive(p) => {
+                world.players.insert(p.id, p);
+            }
+            Record::Leave(id) => world.leave(id),
+            Record::Kick(id) => {
+                world.players.remove(&id);
+            }
+            Record::Latency(id, ticks) => world.set_latency(id, ticks),
+            Record::Wormhole(color) => {
+                world.add_wormhole(color);
+            }
+            Record::End => (),
+        }
+    }
+}
+
+fn to_io<E: Into<Box<dyn std::error::Error + Send + Sync>>>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// Writes a replay file as the match goes
+pub struct Recorder {
+    out: BufWriter<File>,
+}
+
+impl Recorder {
+    pub fn create(path: impl AsRef<Path>, header: &Header) -> io::Result<Recorder> {
+        let mut out = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(&mut out, header).map_err(to_io)?;
+        writeln!(out)?;
+        Ok(Recorder { out })
+    }
+    pub fn record(&mut self, tick: u64, record: Record) -> io::Result<()> {
+        serde_json::to_writer(&mut self.out, &Entry { tick, record }).map_err(to_io)?;
+        writeln!(self.out)
+    }
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// A replay file read back
+pub struct Replay {
+    pub header: Header,
+    pub entries: VecDeque<Entry>,
+}
+
+impl Replay {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Replay> {
+        let mut lines = BufReader::new(File::open(path)?).lines();
+        let header = match lines.next() {
+            Some(line) => serde_json::from_str(&line?).map_err(to_io)?,
+            None => return Err(to_io("empty replay file")),
+        };
+        let mut entries = VecDeque::new();
+        for line in lines {
+            let line = line?;
+            // The last line may be cut short if the server was killed
+            match serde_json::from_str(&line) {
+                Ok(entry) => entries.push_back(entry),
+                Err(_) => break,
+            }
+        }
+        Ok(Replay { header, entries })
+    }
+
+    /// Apply the entries recorded before the next step of `world`.
+    /// Returns false once the recording is over.
+    pub fn advance(&mut self, world: &mut World) -> bool {
+        while let Some(entry) = self.entries.front() {
+            if entry.tick > world.tick {
+                return true;
+            }
+            let entry = self.entries.pop_front().unwrap();
+            if let Record::End = entry.record {
+                self.entries.clear();
+                return false;
+            }
+            entry.record.apply(world);
+        }
+        false
+    }
+}
+
+/// Re-run a replay without any clients and print what happened
+pub fn simulate(path: impl AsRef<Path>) -> io::Result<()> {
+    let mut replay = Replay::open(path)?;
+    let mut world = replay.header.world();
+    while replay.advance(&mut world) {
+        for event in world.step(Vec::new()) {
+            match event {
+                Event::Death(id) => println!("tick {}: player {} died", world.tick, id),
+                Event::Wormhole(i, p) => {
+                    println!(
+                        "tick {}: player {} went into wormhole {}",
+                        world.tick, p.id, i
+                    )
+                }
+                Event::Escape(p) => println!("tick {}: player {} escaped", world.tick, p.id),
+            }
+        }
+    }
+    println!("replay ended at tick {}", world.tick);
+    for p in world.players.values() {
+        println!(
+            "player {} ({}): pos {:?}, health {}, mana {}",
+            p.id,
+            p.name,
+            (p.pos.x, p.pos.y),
+            p.health,
+            p.mana
+        );
+    }
+    if let Some(boss) = &world.boss {
+        println!(
+            "boss: pos {:?}, health {}",
+            (boss.pos.x, boss.pos.y),
+            boss.health
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use na::Vector2;
+    use nalgebra as na;
+
+    /// What a spectator of `world` sees
+    fn seen(world: &World) -> String {
+        let bullets: Vec<_> = world.bullets.iter().map(|b| (b.id, b.pos, b.vel)).collect();
+        format!(
+            "{} {:?}",
+            serde_json::to_string(&world.players).unwrap(),
+            bullets
+        )
+    }
+
+    #[test]
+    fn replays_match_the_recording() {
+        let path = std::env::temp_dir().join(format!("s3-replay-{}.replay", std::process::id()));
+        let header = Header {
+            seed: 3,
+            boss: Bosses::default().get("Normal").cloned(),
+            escapable: false,
+            classes: Classes::default(),
+            map: Map::default(),
+        };
+        let mut recorder = Recorder::create(&path, &header).unwrap();
+        let mut world = header.world();
+        let mut recorded = Vec::new();
+        let mut shots = 0;
+        for tick in 0..300u32 {
+            let id = 1 + (tick / 80) as usize % 2;
+            let record = match tick {
+                0 => Some(Record::Input(
+                    1,
+                    Input::Spawn("alice".to_owned(), "Sniper".to_owned()),
+                )),
+                1 => Some(Record::Input(
+                    2,
+                    Input::Spawn("bob".to_owned(), "Quickshot".to_owned()),
+                )),
+                2 => Some(Record::Latency(2, 6)),
+                240 => Some(Record::Leave(1)),
+                t if t % 40 == 10 => Some(Record::Input(id, Input::Click(t % 80 == 10))),
+                t if t % 15 == 0 => {
+                    let angle = t as f32 / 10.0;
+                    let target = Vector2::new(angle.cos(), angle.sin());
+                    Some(Record::Input(id, Input::Target(target)))
+                }
+                _ => None,
+            };
+            if let Some(record) = record {
+                recorder.record(world.tick, record.clone()).unwrap();
+                record.apply(&mut world);
+            }
+            world.step(Vec::new());
+            shots += world.bullets.len();
+            recorded.push(seen(&world));
+        }
+        recorder.record(world.tick, Record::End).unwrap();
+        recorder.flush().unwrap();
+        assert!(shots > 0);
+
+        // Played back tick by tick, the world is the same as it was
+        let mut replay = Replay::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut played = replay.header.world();
+        let mut replayed = Vec::new();
+        while replay.advance(&mut played) {
+            played.step(Vec::new());
+            replayed.push(seen(&played));
+        }
+        assert_eq!(played.tick, world.tick);
+        assert_eq!(replayed, recorded);
+        assert_eq!(
+            played.boss.as_ref().map(|b| (b.pos, b.health)),
+            world.boss.as_ref().map(|b| (b.pos, b.health))
+        );
+    }
+}
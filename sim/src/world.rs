@@ -0,0 +1,1131 @@
+//! The simulation of one arena, stepped at a fixed tick rate.
+//!
+//! A `World` knows nothing about sessions or actors: it is fed client inputs,
+//! stepped one `TICK` at a time and reports what happened as `Event`s. Timers
+//! count ticks and all randomness comes from a seeded rng, so the same seed
+//! and inputs always produce the same world.
+//!
+//! Shooters see everyone else as they were a round trip ago, so player bullets
+//! are tested against player positions rewound by their owner's latency, up
+//! to `MAX_REWIND`.
+//!
+//! Players are solid: they bounce off each other and off the boss, and ram
+//! what they hit while boosting. Obstacles stop players and the boss, and
+//! absorb bullets.
+use na::Vector2;
+use nalgebra as na;
+use rand::prelude::*;
+use rstar::{RTree, RTreeObject, AABB};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+
+use crate::ability::{Effect, Turret};
+use crate::boss::*;
+use crate::bullet::*;
+use crate::class::Classes;
+use crate::consts::*;
+use crate::ids::IdGen;
+use crate::map::Map;
+use crate::obstacle::Obstacle;
+use crate::player::*;
+use crate::projectile::*;
+
+pub struct Wormhole {
+    pub pos: Vector2<f32>,
+    pub color: u8,
+}
+
+impl Wormhole {
+    const RADIUS: f32 = 30.0;
+}
+
+impl RTreeObject for &Wormhole {
+    type Envelope = AABB<[f32; 2]>;
+
+    fn envelope(&self) -> Self::Envelope {
+        let size = Wormhole::RADIUS;
+        AABB::from_corners(
+            [self.pos.x - size, self.pos.y - size],
+            [self.pos.x + size, self.pos.y + size],
+        )
+    }
+}
+
+/// Where the nearest enemy of player `owner` within `range` of `pos` on `map`
+/// is: the boss at `boss`, or with pvp another player in `players`
+fn nearest_enemy(
+    players: &RTree<&Player>,
+    map: &Map,
+    pvp: bool,
+    boss: Option<Vector2<f32>>,
+    owner: usize,
+    pos: Vector2<f32>,
+    range: f32,
+) -> Option<Vector2<f32>> {
+    let player = if pvp {
+        nearest_player(players, map, pos, range, Some(owner))
+    } else {
+        None
+    };
+    let boss = boss
+        .map(|at| map.closest_copy(at, pos))
+        .filter(|&at| (at - pos).magnitude() <= range);
+    player
+        .into_iter()
+        .chain(boss)
+        .min_by(|a, b| (a - pos).magnitude().total_cmp(&(b - pos).magnitude()))
+}
+
+/// A circle that bumps into others
+struct Body {
+    pos: Vector2<f32>,
+    vel: Vector2<f32>,
+    mass: f32,
+}
+
+impl Body {
+    /// Push `self` and `other`, whose centers are less than `reach` apart,
+    /// out of each other and bounce them off each other elastically.
+    /// Returns how fast they were closing in.
+    fn bounce(&mut self, other: &mut Body, reach: f32) -> f32 {
+        let d = other.pos - self.pos;
+        let normal = d.try_normalize(1.0e-6).unwrap_or_else(Vector2::x);
+        let (wa, wb) = (1.0 / self.mass, 1.0 / other.mass);
+        let overlap = (reach - d.magnitude()).max(0.0);
+        self.pos -= normal * overlap * wa / (wa + wb);
+        other.pos += normal * overlap * wb / (wa + wb);
+
+        let closing = (self.vel - other.vel).dot(&normal);
+        if closing <= 0.0 {
+            return 0.0;
+        }
+        let impulse = 2.0 * closing / (wa + wb);
+        self.vel -= normal * impulse * wa;
+        other.vel += normal * impulse * wb;
+        closing
+    }
+}
+
+/// How far back hits are checked for shooters with high latency
+pub const MAX_REWIND: u32 = ticks(200);
+
+/// How many places are weighed against each other for a spawn, on maps that
+/// don't list spawn points
+const SPAWN_CANDIDATES: usize = 16;
+/// Spawns at least this far from every threat are all as good
+const SPAWN_CLEARANCE: f32 = 500.0;
+/// Spawns closer than this to a wall are worse the closer they are
+const SPAWN_WALL_MARGIN: f32 = 150.0;
+
+/// What players can do
+#[derive(Deserialize, Serialize, Clone)]
+pub enum Input {
+    /// Join the arena with this name and class, unknown classes are ignored
+    Spawn(String, String),
+    /// Point and fly towards this direction
+    Target(Vector2<f32>),
+    Click(bool),
+    /// Hold the first ability
+    Split(bool),
+    Join(bool),
+    Escape(bool),
+    /// Hold the ability in this slot
+    Ability(u8, bool),
+}
+
+/// Things that happened during a step that the outside world needs to act on
+pub enum Event {
+    /// A player died
+    Death(usize),
+    /// A player went into the wormhole with this index
+    Wormhole(usize, Player),
+    /// A player escaped back to the home arena
+    Escape(Player),
+}
+
+pub struct World {
+    /// Seed of the rng, together with the inputs it decides everything
+    pub seed: u64,
+    /// Number of steps taken
+    pub tick: u64,
+    pub players: BTreeMap<usize, Player>,
+    pub bullets: Vec<Bullet>,
+    pub boss: Option<Boss>,
+    pub boss_bullets: Vec<BossBullet>,
+    pub turrets: Vec<Turret>,
+    pub wormholes: Vec<Wormhole>,
+    /// Layout of the arena
+    pub map: Map,
+    /// The obstacles of `map`
+    pub obstacles: RTree<Obstacle>,
+    pub pvp_enabled: bool,
+    /// Whether holding escape takes players back to a home arena
+    pub escapable: bool,
+    /// What players can spawn as
+    pub classes: Classes,
+    rng: StdRng,
+    ids: IdGen,
+    /// Ticks until a dead boss comes back
+    boss_respawn: u32,
+    /// Measured round trip of each player's client, in ticks
+    latency: BTreeMap<usize, u32>,
+    /// Player positions after the last `MAX_REWIND` steps, newest first
+    history: VecDeque<Vec<(usize, Vector2<f32>)>>,
+}
+
+impl World {
+    pub fn new(seed: u64, map: Map, boss: Option<BossClass>, escapable: bool) -> World {
+        let mut rng = StdRng::seed_from_u64(seed);
+        World {
+            seed,
+            tick: 0,
+            players: BTreeMap::new(),
+            bullets: Vec::new(),
+            boss: boss.map(|class| Boss::new(map.boss_spawn(&mut rng), class)),
+            boss_bullets: Vec::new(),
+            turrets: Vec::new(),
+            wormholes: Vec::new(),
+            obstacles: RTree::bulk_load(map.obstacles.clone()),
+            map,
+            pvp_enabled: true,
+            escapable,
+            classes: Classes::default(),
+            rng,
+            ids: IdGen::default(),
+            boss_respawn: 0,
+            latency: BTreeMap::new(),
+            history: VecDeque::new(),
+        }
+    }
+
+    /// Set the round trip of the client of player `id`
+    pub fn set_latency(&mut self, id: usize, ticks: u32) {
+        self.latency.insert(id, ticks);
+    }
+
+    /// Remove everything about a client that left
+    pub fn leave(&mut self, id: usize) {
+        self.players.remove(&id);
+        self.latency.remove(&id);
+    }
+
+    /// How many ticks hits from bullets of player `id` are rewound
+    fn rewind(&self, id: usize) -> u32 {
+        self.latency.get(&id).map_or(0, |&t| t.min(MAX_REWIND))
+    }
+
+    /// Where player `id` was `rewind` ticks ago, as far as the history goes
+    fn position_at(&self, id: usize, rewind: u32) -> Option<Vector2<f32>> {
+        let mut at = None;
+        for positions in self.history.iter().take(rewind as usize + 1) {
+            match positions.binary_search_by_key(&id, |(i, _)| *i) {
+                Ok(i) => at = Some(positions[i].1),
+                // Not born yet, the oldest position seen is the best guess
+                Err(_) => break,
+            }
+        }
+        at
+    }
+
+    /// Open a new wormhole where the map says
+    pub fn add_wormhole(&mut self, color: u8) -> &Wormhole {
+        let pos = self.map.wormhole(self.wormholes.len(), &mut self.rng);
+        self.wormholes.push(Wormhole { pos, color });
+        self.wormholes.last().unwrap()
+    }
+
+    /// The safest of the places the map lets a player spawn: clear of the
+    /// obstacles, the furthest from enemies, bullets and the boss, up to
+    /// `SPAWN_CLEARANCE`, and off the walls
+    fn spawn_point(&mut self) -> Vector2<f32> {
+        let candidates = self.map.spawn_candidates(SPAWN_CANDIDATES, &mut self.rng);
+        let (obstacles, map) = (&self.obstacles, &self.map);
+        let pvp = self.pvp_enabled;
+        // Without pvp only the boss and its bullets are a danger
+        let pt = RTree::bulk_load(self.players.values().filter(|_| pvp).collect());
+        let bt = RTree::bulk_load(self.bullets.iter().filter(|_| pvp).collect());
+        let bbt = RTree::bulk_load(self.boss_bullets.iter().collect());
+        let boss = self.boss.as_ref().filter(|b| b.health > 0).map(|b| b.pos);
+
+        let safety = |pos: Vector2<f32>| {
+            let blocked = map
+                .around(pos, Vector2::repeat(Player::RADIUS))
+                .iter()
+                .any(|envelope| {
+                    obstacles
+                        .locate_in_envelope_intersecting(envelope)
+                        .any(|o| {
+                            o.push_out(map.closest_copy(pos, o.pos), Player::RADIUS)
+                                .is_some()
+                        })
+                });
+            if blocked {
+                return f32::NEG_INFINITY;
+            }
+            let mut threats: Vec<Vector2<f32>> = Vec::new();
+            for envelope in map.around(pos, Vector2::repeat(SPAWN_CLEARANCE)) {
+                threats.extend(pt.locate_in_envelope_intersecting(&envelope).map(|p| p.pos));
+                threats.extend(bt.locate_in_envelope_intersecting(&envelope).map(|b| b.pos));
+                threats.extend(
+                    bbt.locate_in_envelope_intersecting(&envelope)
+                        .map(|b| b.pos),
+                );
+            }
+            let boss = boss.map(|at| map.distance(pos, at) - Boss::RADIUS);
+            let nearest = threats
+                .into_iter()
+                .map(|at| map.distance(pos, at))
+                .chain(boss)
+                .fold(SPAWN_CLEARANCE, f32::min);
+            nearest + map.wall_distance(pos).min(SPAWN_WALL_MARGIN)
+        };
+        let mut best = (map.size / 2.0, f32::NEG_INFINITY);
+        for pos in candidates {
+            let score = safety(pos);
+            if score > best.1 {
+                best = (pos, score);
+            }
+        }
+        best.0
+    }
+
+    /// Apply an input from the player with this id
+    pub fn input(&mut self, id: usize, m: Input) {
+        if let Input::Spawn(n, c) = m {
+            if self.classes.get(&c).is_none() {
+                return;
+            }
+            let pos = self.spawn_point();
+            self.players.insert(id, Player::new(id, pos, n, c));
+        } else if let Some(p) = self.players.get_mut(&id) {
+            match m {
+                Input::Click(b) => p.mouse = b,
+                Input::Split(b) => p.hold(0, b),
+                Input::Ability(slot, b) => p.hold(slot as usize, b),
+                Input::Target(v) => p.target = v,
+                Input::Escape(b) => {
+                    if !b {
+                        p.escape_time = None
+                    } else if p.escape_time.is_none() {
+                        p.escape_time = Some(0)
+                    }
+                }
+                Input::Join(b) => p.join = b,
+                Input::Spawn(_, _) => unreachable!(),
+            }
+        }
+    }
+
+    /// Apply the inputs of players since the last step, in order, and step
+    pub fn step(&mut self, inputs: Vec<(usize, Input)>) -> Vec<Event> {
+        for (id, m) in inputs {
+            self.input(id, m);
+        }
+        self.tick += 1;
+        let mut events = Vec::new();
+
+        if let Some(boss) = &mut self.boss {
+            if boss.health > 0 {
+                if !self.players.is_empty() {
+                    boss.tick(
+                        &self.map,
+                        &mut self.ids,
+                        &mut self.boss_bullets,
+                        self.players.values().peekable(),
+                    );
+                }
+            } else if self.boss_respawn > 0 {
+                self.boss_respawn -= 1;
+            } else {
+                *boss = Boss::new(self.map.boss_spawn(&mut self.rng), boss.class.clone());
+            }
+        }
+
+        // Health regenerates 1 per 48ms, mana as fast as the class says
+        let ht = self.tick.is_multiple_of(3) as u8;
+        let mut cast = Vec::new();
+        for p in self.players.values_mut() {
+            let class = self.classes.get_or_first(&p.class);
+            let effects = p.tick(
+                class,
+                &self.map,
+                &mut self.rng,
+                &mut self.ids,
+                &mut self.bullets,
+            );
+            for effect in effects {
+                cast.push((p.id, p.pos, effect));
+            }
+
+            p.health = p.health.saturating_add(ht);
+            p.mana = p.mana.saturating_add(class.mana_gain(self.tick));
+        }
+        self.cast_abilities(cast);
+        self.fire_turrets();
+        self.collide_bodies();
+        self.block_bodies();
+
+        self.history
+            .push_front(self.players.values().map(|p| (p.id, p.pos)).collect());
+        self.history.truncate(MAX_REWIND as usize + 1);
+
+        self.move_bullets();
+        self.absorb_bullets();
+
+        self.collision_trees(&mut events);
+
+        self.reap_players(&mut events);
+
+        self.escape_players(&mut events);
+
+        self.bullets.retain(|b| b.ttl > 0);
+        self.boss_bullets.retain(|b| b.ttl > 0);
+
+        events
+    }
+
+    fn escape_players(&mut self, events: &mut Vec<Event>) {
+        let mut escapers = Vec::new();
+        for (i, p) in self.players.iter_mut() {
+            if let Some(time) = &mut p.escape_time {
+                *time += 1;
+                if *time > ticks(1000) {
+                    escapers.push(*i)
+                }
+            }
+        }
+        if self.escapable {
+            for escaper in escapers {
+                if let Some(p) = self.players.remove(&escaper) {
+                    self.latency.remove(&escaper);
+                    events.push(Event::Escape(p));
+                }
+            }
+        }
+    }
+    /// Separate players that overlap each other or the boss, bouncing them
+    /// apart, and let the ones that boost hurt what they ram
+    fn collide_bodies(&mut self) {
+        let map = &self.map;
+        let pt = RTree::bulk_load(self.players.values().collect());
+        let reach = 2.0 * Player::RADIUS;
+        let mut pairs = Vec::new();
+        for p in self.players.values() {
+            for envelope in map.around(p.pos, Vector2::repeat(reach)) {
+                for q in pt.locate_in_envelope_intersecting(&envelope) {
+                    if p.id < q.id && map.distance(p.pos, q.pos) < reach {
+                        pairs.push((p.id, q.id));
+                    }
+                }
+            }
+        }
+        drop(pt);
+
+        // Players fly `speed` times their `vel` while boosting, bodies move
+        // at what it adds up to. Bodies are placed next to each other, on a
+        // torus that can be across the seam.
+        let body = |p: &Player, speed: f32, near: Vector2<f32>| Body {
+            pos: map.closest_copy(p.pos, near),
+            vel: p.vel * speed,
+            mass: Player::MASS,
+        };
+        let settle = |p: &mut Player, b: Body, speed: f32| {
+            p.pos = map.confine(b.pos);
+            p.vel = b.vel / speed;
+        };
+        let mut damage = Vec::new();
+        for (a, b) in pairs {
+            let (pa, pb) = (&self.players[&a], &self.players[&b]);
+            let (ca, cb) = (
+                self.classes.get_or_first(&pa.class),
+                self.classes.get_or_first(&pb.class),
+            );
+            let (sa, sb) = (pa.speed(ca), pb.speed(cb));
+            let (mut ba, mut bb) = (body(pa, sa, pa.pos), body(pb, sb, pa.pos));
+            let closing = ba.bounce(&mut bb, reach);
+            if self.pvp_enabled {
+                if sa > 1.0 {
+                    damage.push((b, ca.ram * closing));
+                }
+                if sb > 1.0 {
+                    damage.push((a, cb.ram * closing));
+                }
+            }
+            settle(self.players.get_mut(&a).unwrap(), ba, sa);
+            settle(self.players.get_mut(&b).unwrap(), bb, sb);
+        }
+
+        if let Some(boss) = self.boss.as_mut().filter(|b| b.health > 0) {
+            let reach = Player::RADIUS + Boss::RADIUS;
+            let mut boss_damage = 0.0;
+            for p in self.players.values_mut() {
+                if map.distance(p.pos, boss.pos) >= reach {
+                    continue;
+                }
+                let class = self.classes.get_or_first(&p.class);
+                let speed = p.speed(class);
+                let mut bp = body(p, speed, p.pos);
+                let mut bb = Body {
+                    pos: map.closest_copy(boss.pos, p.pos),
+                    vel: boss.vel,
+                    mass: Boss::MASS,
+                };
+                let closing = bp.bounce(&mut bb, reach);
+                if speed > 1.0 {
+                    boss_damage += class.ram * closing;
+                }
+                settle(p, bp, speed);
+                boss.pos = map.confine(bb.pos);
+                boss.vel = bb.vel;
+            }
+            if boss_damage > 0.0 {
+                self.hurt_boss(boss_damage.round() as u8, Vector2::zeros(), 1.0);
+            }
+        }
+
+        for (id, d) in damage {
+            if let Some(p) = self.players.get_mut(&id) {
+                p.hurt(self.classes.get_or_first(&p.class), d.round() as u8);
+            }
+        }
+    }
+
+    /// Move players and the boss out of the obstacles they flew into, and
+    /// stop them from flying further in
+    fn block_bodies(&mut self) {
+        let (obstacles, map) = (&self.obstacles, &self.map);
+        let block = |pos: &mut Vector2<f32>, vel: &mut Vector2<f32>, radius: f32| {
+            for envelope in map.around(*pos, Vector2::repeat(radius)) {
+                for o in obstacles.locate_in_envelope_intersecting(&envelope) {
+                    if let Some(push) = o.push_out(map.closest_copy(*pos, o.pos), radius) {
+                        *pos = map.wrap(*pos + push);
+                        let out = push.try_normalize(1.0e-6).unwrap_or_else(Vector2::zeros);
+                        *vel -= out * vel.dot(&out).min(0.0);
+                    }
+                }
+            }
+        };
+        for p in self.players.values_mut() {
+            block(&mut p.pos, &mut p.vel, Player::RADIUS);
+        }
+        if let Some(boss) = &mut self.boss {
+            block(&mut boss.pos, &mut boss.vel, Boss::RADIUS);
+        }
+    }
+
+    /// Take out the bullets that flew into an obstacle or out of the arena
+    fn absorb_bullets(&mut self) {
+        let (obstacles, map) = (&self.obstacles, &self.map);
+        let absorbed = |pos: Vector2<f32>, radius: f32| {
+            !map.contains(pos)
+                || map
+                    .around(pos, Vector2::repeat(radius))
+                    .iter()
+                    .any(|envelope| {
+                        obstacles
+                            .locate_in_envelope_intersecting(envelope)
+                            .any(|o| o.push_out(map.closest_copy(pos, o.pos), radius).is_some())
+                    })
+        };
+        self.bullets.retain(|b| !absorbed(b.pos, Bullet::RADIUS));
+        self.boss_bullets
+            .retain(|b| !absorbed(b.pos, BossBullet::RADIUS));
+    }
+
+    /// Act on the abilities players cast that affect more than themselves
+    fn cast_abilities(&mut self, cast: Vec<(usize, Vector2<f32>, Effect)>) {
+        for (id, pos, effect) in cast {
+            match effect {
+                Effect::Heal { radius, amount } => {
+                    let (pvp, map) = (self.pvp_enabled, &self.map);
+                    for p in self.players.values_mut() {
+                        if p.id == id || (!pvp && map.distance(pos, p.pos) <= radius) {
+                            p.health = p.health.saturating_add(amount);
+                        }
+                    }
+                }
+                Effect::Turret {
+                    duration,
+                    range,
+                    rate,
+                } => self.turrets.push(Turret {
+                    id: self.ids.next_id(),
+                    pos,
+                    owner: id,
+                    range,
+                    rate: ticks(rate),
+                    reload: 0,
+                    ttl: ticks(duration),
+                }),
+                Effect::Boost { .. } | Effect::Shield { .. } | Effect::Blink { .. } => (),
+            }
+        }
+    }
+
+    /// Let turrets shoot, and take down the ones that ran out or whose owner
+    /// is gone
+    fn fire_turrets(&mut self) {
+        let players = &self.players;
+        self.turrets
+            .retain(|t| t.ttl > 0 && players.contains_key(&t.owner));
+        let pt = RTree::bulk_load(self.players.values().collect());
+        let boss = self.boss.as_ref().filter(|b| b.health > 0).map(|b| b.pos);
+        for t in self.turrets.iter_mut() {
+            t.ttl -= 1;
+            t.reload = t.reload.saturating_sub(1);
+            if t.reload > 0 {
+                continue;
+            }
+            let at = nearest_enemy(
+                &pt,
+                &self.map,
+                self.pvp_enabled,
+                boss,
+                t.owner,
+                t.pos,
+                t.range,
+            );
+            if let Some(at) = at {
+                let class = self.classes.get_or_first(&self.players[&t.owner].class);
+                let vel = (at - t.pos)
+                    .try_normalize(1.0e-6)
+                    .unwrap_or_else(Vector2::y)
+                    * class.speed;
+                let id = self.ids.next_id();
+                self.bullets
+                    .push(Bullet::new(class, id, t.owner, t.pos, vel));
+                t.reload = t.rate;
+            }
+        }
+    }
+
+    /// Damage, push and slow down the boss if it is alive, shields only stop
+    /// the damage
+    fn hurt_boss(&mut self, damage: u8, push: Vector2<f32>, slow: f32) {
+        if let Some(boss) = self.boss.as_mut().filter(|b| b.health > 0) {
+            if !boss.shielded() {
+                boss.health = boss.health.saturating_sub(damage);
+            }
+            boss.vel = (boss.vel + push) * slow;
+            if boss.health == 0 {
+                self.boss_respawn = ticks(3000);
+            }
+        }
+    }
+
+    /// Fly all bullets one tick, then set off the ones that detonate and
+    /// apply slowing fields
+    fn move_bullets(&mut self) {
+        let pt = RTree::bulk_load(self.players.values().collect());
+        let boss = self.boss.as_ref().filter(|b| b.health > 0).map(|b| b.pos);
+        let pvp = self.pvp_enabled;
+        let map = &self.map;
+        // Whether the boss is within `radius` of `pos`
+        let near_boss = |pos: Vector2<f32>, radius: f32| {
+            boss.is_some_and(|at| map.distance(pos, at) <= radius + Boss::RADIUS)
+        };
+
+        for b in self.bullets.iter_mut() {
+            let owner = b.owner;
+            fly(&mut b.pos, &mut b.vel, &b.behaviors, map, |pos, range| {
+                nearest_enemy(&pt, map, pvp, boss, owner, pos, range)
+            });
+            b.ttl = b.ttl.saturating_sub(1);
+            b.age += 1;
+        }
+        for b in self.boss_bullets.iter_mut() {
+            fly(&mut b.pos, &mut b.vel, &b.behaviors, map, |pos, range| {
+                nearest_player(&pt, map, pos, range, None)
+            });
+            b.ttl = b.ttl.saturating_sub(1);
+            b.age += 1;
+        }
+
+        let mut damage: Vec<(usize, u8)> = Vec::new();
+        let mut slow: BTreeMap<usize, f32> = BTreeMap::new();
+        let mut boss_damage = 0u8;
+        let mut boss_slow = 1.0f32;
+        let bullets = self.bullets.iter_mut().map(|b| {
+            let owner = Some(b.owner).filter(|_| pvp);
+            (&mut b.ttl, b.pos, b.age, &b.behaviors, owner, true)
+        });
+        let boss_bullets = self
+            .boss_bullets
+            .iter_mut()
+            .map(|b| (&mut b.ttl, b.pos, b.age, &b.behaviors, None, false));
+        for (ttl, pos, age, behaviors, owner, player_bullet) in bullets.chain(boss_bullets) {
+            // Without pvp the bullets of players only affect the boss
+            let hits_players = !player_bullet || pvp;
+            if let Some((radius, d)) = detonation(behaviors, age) {
+                *ttl = 0;
+                if hits_players {
+                    for id in players_within(&pt, map, pos, radius, owner) {
+                        damage.push((id, d));
+                    }
+                }
+                if player_bullet && near_boss(pos, radius) {
+                    boss_damage = boss_damage.saturating_add(d);
+                }
+            }
+            for (radius, factor) in fields(behaviors) {
+                if hits_players {
+                    for id in players_within(&pt, map, pos, radius, owner) {
+                        let s = slow.entry(id).or_insert(1.0);
+                        *s = s.min(factor);
+                    }
+                }
+                if player_bullet && near_boss(pos, radius) {
+                    boss_slow = boss_slow.min(factor);
+                }
+            }
+        }
+        drop(pt);
+        // What went off is gone, rather than also hitting something directly
+        self.bullets
+            .retain(|b| detonation(&b.behaviors, b.age).is_none());
+        self.boss_bullets
+            .retain(|b| detonation(&b.behaviors, b.age).is_none());
+
+        for (id, d) in damage {
+            if let Some(p) = self.players.get_mut(&id) {
+                p.hurt(self.classes.get_or_first(&p.class), d);
+            }
+        }
+        for (id, s) in slow {
+            if let Some(p) = self.players.get_mut(&id) {
+                p.vel *= s;
+            }
+        }
+        if boss_damage > 0 || boss_slow < 1.0 {
+            self.hurt_boss(boss_damage, Vector2::zeros(), boss_slow);
+        }
+    }
+
+    fn collision_trees(&mut self, events: &mut Vec<Event>) {
+        let map = &self.map;
+        let pt = RTree::bulk_load(self.players.values().collect());
+
+        let mut move_players = Vec::new();
+        for w in &self.wormholes {
+            let mut wv = Vec::new();
+            for envelope in map.around(w.pos, Vector2::repeat(Wormhole::RADIUS)) {
+                for intersect in pt.locate_in_envelope_intersecting(&envelope) {
+                    if intersect.join
+                        && map.distance(w.pos, intersect.pos)
+                            <= (Player::RADIUS + Wormhole::RADIUS).powf(2.0)
+                    {
+                        wv.push(intersect.id);
+                    }
+                }
+            }
+            move_players.push(wv);
+        }
+        for (i, pl) in move_players.iter().enumerate() {
+            for pi in pl {
+                if let Some(p) = self.players.remove(pi) {
+                    self.latency.remove(pi);
+                    events.push(Event::Wormhole(i, p));
+                }
+            }
+        }
+
+        let dt = RTree::bulk_load(self.bullets.iter().collect());
+        let dbt = RTree::bulk_load(self.boss_bullets.iter().collect());
+
+        let mut health_map = HashMap::new();
+        let mut health_add = HashMap::new();
+        let mut delete_bullets = HashSet::new();
+        let mut delete_boss_bullets = HashSet::new();
+        // Bullets touching something they haven't hit yet, resolved in order
+        // below since piercing bullets only go through so many targets
+        let mut hits = Vec::new();
+        let boss_alive = self.boss.as_ref().is_some_and(|b| b.health > 0);
+        if let Some(boss) = self.boss.as_ref().filter(|_| boss_alive) {
+            for envelope in map.around(boss.pos, Vector2::repeat(Boss::RADIUS)) {
+                for intersect in dt.locate_in_envelope_intersecting(&envelope) {
+                    if map.distance(boss.pos, intersect.pos)
+                        <= (Boss::RADIUS + Bullet::RADIUS).powf(2.0)
+                        && !intersect.hit.contains(&Target::Boss)
+                    {
+                        hits.push((intersect.id, Target::Boss));
+                    }
+                }
+            }
+        }
+        // Bullets are checked once for each distinct rewind of their owners
+        let rewinds: BTreeSet<u32> = std::iter::once(0)
+            .chain(self.latency.keys().map(|id| self.rewind(*id)))
+            .collect();
+        for (i, p) in &self.players {
+            if self.pvp_enabled {
+                for &rewind in &rewinds {
+                    let pos = self.position_at(*i, rewind).unwrap_or(p.pos);
+                    let intersecting = map
+                        .around(pos, Vector2::repeat(Player::RADIUS))
+                        .into_iter()
+                        .flat_map(|envelope| dt.locate_in_envelope_intersecting(&envelope));
+                    for intersect in intersecting {
+                        if intersect.owner != p.id
+                            && self.rewind(intersect.owner) == rewind
+                            && map.distance(pos, intersect.pos)
+                                <= (Player::RADIUS + Bullet::RADIUS).powf(2.0)
+                            && !intersect.hit.contains(&Target::Player(*i))
+                        {
+                            hits.push((intersect.id, Target::Player(*i)));
+                        }
+                    }
+                }
+            }
+            let intersecting = map
+                .around(p.pos, Vector2::repeat(Player::RADIUS))
+                .into_iter()
+                .flat_map(|envelope| dbt.locate_in_envelope_intersecting(&envelope));
+            for intersect in intersecting {
+                if map.distance(p.pos, intersect.pos)
+                    <= (Player::RADIUS + BossBullet::RADIUS).powf(2.0)
+                {
+                    if let Some(boss) = &mut self.boss {
+                        if boss.health > 0 {
+                            boss.health = boss.health.saturating_add(20);
+                        }
+                    }
+                    let h = health_map.entry(*i).or_insert(0u8);
+                    *h = h.saturating_add(50);
+
+                    delete_boss_bullets.insert(intersect.id);
+                }
+            }
+        }
+
+        let index: HashMap<usize, usize> = self
+            .bullets
+            .iter()
+            .enumerate()
+            .map(|(n, b)| (b.id, n))
+            .collect();
+        for (id, target) in hits {
+            let b = &mut self.bullets[index[&id]];
+            // Spent on an earlier hit this step
+            if delete_bullets.contains(&id) || b.hit.contains(&target) {
+                continue;
+            }
+            b.hit.push(target);
+            if b.pierce == 0 {
+                delete_bullets.insert(id);
+            } else {
+                b.pierce -= 1;
+            }
+            let push = b.vel.try_normalize(1.0e-6).unwrap_or_else(Vector2::zeros) * b.knockback;
+            let (damage, owner, lifesteal) = (b.damage, b.owner, b.lifesteal);
+            // Shields stop the bullet, but leave nothing to drain
+            let drained = match target {
+                Target::Boss => self
+                    .boss
+                    .as_ref()
+                    .is_some_and(|b| b.health > 0 && !b.shielded()),
+                Target::Player(i) => self
+                    .players
+                    .get(&i)
+                    .is_some_and(|p| !p.shielded(self.classes.get_or_first(&p.class))),
+            };
+            match target {
+                Target::Boss => self.hurt_boss(damage, push, 1.0),
+                Target::Player(i) => {
+                    let h = health_map.entry(i).or_insert(0u8);
+                    *h = h.saturating_add(damage);
+                    if let Some(p) = self.players.get_mut(&i) {
+                        p.vel += push;
+                    }
+                }
+            }
+            if drained {
+                let h = health_add.entry(owner).or_insert(0u8);
+                *h = h.saturating_add((damage as f32 * lifesteal).round() as u8);
+            }
+        }
+
+        for (i, h) in &health_map {
+            if let Some(p) = self.players.get_mut(i) {
+                p.hurt(self.classes.get_or_first(&p.class), *h);
+            }
+        }
+        for (i, h) in &health_add {
+            self.players
+                .entry(*i)
+                .and_modify(|p| p.health = p.health.saturating_add(*h));
+        }
+        self.bullets.retain(|b| !delete_bullets.contains(&b.id));
+        self.boss_bullets
+            .retain(|b| !delete_boss_bullets.contains(&b.id));
+    }
+    fn reap_players(&mut self, events: &mut Vec<Event>) {
+        self.players.retain(|i, p| {
+            if p.health == 0 {
+                events.push(Event::Death(*i));
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::boss::Bosses;
+    use crate::map::Zone;
+    use crate::obstacle::{Obstacle, Shape};
+
+    /// Everything that can differ between two worlds, to compare them by
+    fn state(world: &World) -> String {
+        let bullets: Vec<_> = world
+            .bullets
+            .iter()
+            .map(|b| (b.id, b.owner, b.pos, b.vel, b.ttl, b.pierce, &b.hit))
+            .collect();
+        let boss_bullets: Vec<_> = world
+            .boss_bullets
+            .iter()
+            .map(|b| (b.id, b.pos, b.vel, b.ttl))
+            .collect();
+        let boss = world
+            .boss
+            .as_ref()
+            .map(|b| (b.pos, b.vel, b.health, b.phase));
+        let turrets: Vec<_> = world
+            .turrets
+            .iter()
+            .map(|t| (t.id, t.owner, t.pos, t.reload, t.ttl))
+            .collect();
+        format!(
+            "{} {:?} {:?} {:?} {:?}",
+            serde_json::to_string(&world.players).unwrap(),
+            bullets,
+            boss_bullets,
+            boss,
+            turrets
+        )
+    }
+
+    /// Put a player of `class` at `pos`, past its spawn protection
+    fn place(world: &mut World, id: usize, pos: Vector2<f32>, class: &str) {
+        let mut p = Player::new(id, pos, format!("p{}", id), class.to_owned());
+        p.invulnerable = 0;
+        world.players.insert(id, p);
+    }
+
+    /// Three players that spawn, fly around, shoot and cast their
+    /// abilities, the same way whatever the seed
+    fn script(tick: u64) -> Vec<(usize, Input)> {
+        let mut inputs = Vec::new();
+        for id in 1..=3 {
+            let t = tick + id as u64 * 40;
+            let input = match t % 120 {
+                0 => Input::Spawn(format!("p{}", id), "Quickshot".to_owned()),
+                30 => Input::Click(true),
+                50 => Input::Target(Vector2::new((t % 7) as f32 - 3.0, (t % 5) as f32 - 2.0)),
+                70 => Input::Ability((t % 3) as u8, true),
+                90 => Input::Ability((t % 3) as u8, false),
+                110 => Input::Click(false),
+                _ => continue,
+            };
+            inputs.push((id, input));
+        }
+        inputs
+    }
+
+    fn run(seed: u64, ticks: u64) -> Vec<String> {
+        let boss = Bosses::default().get("Hardcore").cloned();
+        let mut world = World::new(seed, Map::default(), boss, false);
+        (0..ticks)
+            .map(|tick| {
+                world.step(script(tick));
+                state(&world)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn the_seed_and_inputs_decide_everything() {
+        let a = run(7, 600);
+        assert_eq!(a, run(7, 600));
+        assert!(a.last().unwrap().contains("\"p3\""));
+        // Only the rng tells another seed apart
+        let b = run(8, 600);
+        assert_ne!(a.last(), b.last());
+    }
+
+    /// Whether a still bullet of a shooter with `latency` lands on where its
+    /// target stood until `moved` ticks ago, before it was moved away
+    fn lands_where_the_target_was(latency: u32, moved: u32) -> bool {
+        let mut world = World::new(1, Map::default(), None, false);
+        place(&mut world, 1, Vector2::new(200.0, 200.0), "Sniper");
+        place(&mut world, 2, Vector2::new(800.0, 1600.0), "Sniper");
+        world.set_latency(1, latency);
+        // The target sits against the bottom wall it flies into
+        for _ in 0..20 {
+            world.step(Vec::new());
+        }
+        let was = world.players[&2].pos;
+        world.players.get_mut(&2).unwrap().pos.x += 400.0;
+        for _ in 1..moved {
+            world.step(Vec::new());
+        }
+        let class = world.classes.get_or_first("Sniper");
+        let bullet = Bullet::new(class, world.ids.next_id(), 1, was, Vector2::zeros());
+        world.bullets.push(bullet);
+        world.step(Vec::new());
+        world.players[&2].health < 255
+    }
+
+    #[test]
+    fn shots_hit_where_the_target_was_a_round_trip_ago() {
+        assert!(!lands_where_the_target_was(0, 1));
+        assert!(lands_where_the_target_was(5, 5));
+        assert!(!lands_where_the_target_was(5, 6));
+        // No further back than `MAX_REWIND`
+        assert!(lands_where_the_target_was(1000, MAX_REWIND));
+        assert!(!lands_where_the_target_was(1000, MAX_REWIND + 1));
+    }
+
+    #[test]
+    fn steps_apply_inputs_and_report_events() {
+        let mut world = World::new(1, Map::default(), None, true);
+        let spawn = Input::Spawn("alice".to_owned(), "Sniper".to_owned());
+        // Inputs apply in order, and only to players that are there
+        world.step(vec![
+            (1, spawn),
+            (1, Input::Target(Vector2::x())),
+            (2, Input::Click(true)),
+        ]);
+        assert_eq!(world.players.keys().collect::<Vec<_>>(), [&1]);
+        let start = world.players[&1].pos;
+        for _ in 0..20 {
+            assert!(world.step(Vec::new()).is_empty());
+        }
+        let flown = world.players[&1].pos - start;
+        assert!(flown.x > 50.0 && flown.y.abs() < 1.0, "flew {:?}", flown);
+
+        // Holding escape for a second takes the player out of the world
+        world.step(vec![(1, Input::Escape(true))]);
+        let mut escaped = None;
+        for _ in 0..ticks(1100) {
+            for event in world.step(Vec::new()) {
+                if let Event::Escape(p) = event {
+                    escaped = Some(p.id);
+                }
+            }
+        }
+        assert_eq!(escaped, Some(1));
+        assert!(world.players.is_empty());
+    }
+
+    #[test]
+    fn piercing_bullets_push_and_drain_what_they_hit() {
+        let mut world = World::new(1, Map::default(), None, false);
+        world.classes = Classes::parse(
+            r#"
+            [[class]]
+            name = "Lancer"
+            pattern = "fan"
+            cooldown = 5000
+            bullets = 1
+            speed = 10.0
+            spread = 0.0
+            lifetime = 1000
+            damage = 100
+            pierce = 1
+            knockback = 8.0
+            lifesteal = 0.25
+            mana_regen = 20
+            "#,
+        )
+        .unwrap();
+        // Carol and then bob are in the line of fire of alice, who is hurt
+        let (alice, carol, bob) = (1, 2, 3);
+        for (id, x) in [(alice, 800.0), (carol, 700.0), (bob, 600.0)] {
+            place(&mut world, id, Vector2::new(x, 800.0), "Lancer");
+        }
+        world.players.get_mut(&alice).unwrap().health = 100;
+
+        let mut inputs = vec![
+            (alice, Input::Target(-Vector2::x())),
+            (alice, Input::Click(true)),
+        ];
+        let mut hit = Vec::new();
+        loop {
+            let health = |world: &World, id| world.players[&id].health;
+            let before: Vec<u8> = [alice, carol, bob]
+                .iter()
+                .map(|&id| health(&world, id))
+                .collect();
+            world.step(std::mem::replace(
+                &mut inputs,
+                vec![(alice, Input::Click(false))],
+            ));
+            for (i, &id) in [carol, bob].iter().enumerate() {
+                if health(&world, id) < before[i + 1] {
+                    hit.push(id);
+                    // Healing the shooter by a quarter of the damage
+                    assert!(health(&world, alice) >= before[0] + 25);
+                    assert!(world.players[&id].vel.x < -5.0);
+                }
+            }
+            if world.bullets.is_empty() {
+                break;
+            }
+        }
+        assert_eq!(hit, [carol, bob]);
+    }
+
+    /// Where alice ends up spawning on `map`, with `boss` around
+    fn spawn(map: Map, boss: Option<&str>) -> Player {
+        let boss = boss.and_then(|name| Bosses::default().get(name).cloned());
+        let mut world = World::new(1, map, boss, false);
+        let spawn = Input::Spawn("alice".to_owned(), "Sniper".to_owned());
+        world.step(vec![(1, spawn)]);
+        world.players[&1].clone()
+    }
+
+    #[test]
+    fn players_spawn_away_from_danger() {
+        let map = Map {
+            spawns: vec![Vector2::new(300.0, 300.0), Vector2::new(1300.0, 1300.0)],
+            boss_zones: vec![Zone {
+                pos: Vector2::new(300.0, 300.0),
+                radius: 0.0,
+            }],
+            ..Map::default()
+        };
+        // The spawn point the boss sits on is passed over, and the player is
+        // protected for a moment
+        let p = spawn(map, Some("Normal"));
+        let d = (p.pos - Vector2::new(1300.0, 1300.0)).magnitude();
+        assert!(d < 10.0, "spawned {} from the safe spawn point", d);
+        assert_eq!(p.invulnerable, Player::INVULNERABLE - 1);
+    }
+
+    #[test]
+    fn players_spawn_clear_of_obstacles() {
+        let rock = Obstacle {
+            pos: Vector2::new(300.0, 300.0),
+            shape: Shape::Circle(100.0),
+            model: "meteorFull".to_owned(),
+        };
+        let map = Map {
+            spawns: vec![Vector2::new(300.0, 300.0), Vector2::new(1300.0, 1500.0)],
+            obstacles: vec![rock],
+            ..Map::default()
+        };
+        // The spawn point in the rock is further from the walls, but passed
+        // over
+        let p = spawn(map, None);
+        let d = (p.pos - Vector2::new(1300.0, 1500.0)).magnitude();
+        assert!(d < 10.0, "spawned {} from the open spawn point", d);
+    }
+}
@@ -1 +1,22 @@
-pub const WORLDSIZE: f32 = 1600.0;
+use std::time::Duration;
+
+pub use s3_sim::consts::*;
+
+/// How often clients are pinged to measure their latency
+pub const PING_INTERVAL: Duration = Duration::from_secs(1);
+/// Default time a client may go without sending anything before it is dropped
+pub const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Default time a player may go without any input before it is taken out
+pub const AFK_TIMEOUT: Duration = Duration::from_secs(120);
+/// Default number of players bots keep the home arena at
+pub const MIN_PLAYERS: usize = 4;
+/// Obstacles in arenas generated from a seed
+pub const OBSTACLES: usize = 12;
+/// How long a dropped client has to come back with its resume token
+pub const RESUME_GRACE: Duration = Duration::from_secs(30);
+
+/// Where the camera of clients without a player looks
+pub const LOBBY_POS: [f32; 2] = [400.0, 400.0];
+/// Default size of the area around its player a client is sent, the client
+/// camera is 1600 wide so this leaves a margin for things flying into view
+pub const VIEW_SIZE: [f32; 2] = [2000.0, 2000.0];
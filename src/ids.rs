@@ -0,0 +1,18 @@
+//! Ids handed out to sessions.
+//!
+//! Session ids are shared by every `GameServer` so a player keeps a unique id
+//! when it travels through a wormhole. They also name the player of the
+//! session in its `World`, whose own ids come from `s3_sim::ids::IdGen`.
+//! Servers playing back a match skip the ids of its players.
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+pub fn next_id() -> usize {
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Never hand out `id` or any id below it from now on
+pub fn skip_past(id: usize) {
+    NEXT_ID.fetch_max(id + 1, Ordering::Relaxed);
+}
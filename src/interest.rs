@@ -0,0 +1,88 @@
+//! Area of interest culling, each session is only sent what is around it.
+use crate::protocol::Playfield;
+use na::Vector2;
+use nalgebra as na;
+use rstar::primitives::GeomWithData;
+use rstar::{Envelope, RTree};
+use s3_sim::map::Map;
+
+type Indexed = GeomWithData<[f32; 2], usize>;
+
+/// Spatial index over the players and bullets of one snapshot
+pub struct Interest {
+    players: RTree<Indexed>,
+    bullets: RTree<Indexed>,
+}
+
+impl Interest {
+    pub fn new(playfield: &Playfield) -> Interest {
+        Interest {
+            players: RTree::bulk_load(
+                playfield
+                    .players
+                    .iter()
+                    .enumerate()
+                    .map(|(i, p)| Indexed::new([p.pos.x, p.pos.y], i))
+                    .collect(),
+            ),
+            bullets: RTree::bulk_load(
+                playfield
+                    .bullets
+                    .iter()
+                    .enumerate()
+                    .map(|(i, b)| Indexed::new([b.pos.x, b.pos.y], i))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// The part of `playfield` inside the rectangle `center ± half`, which
+    /// wraps around the seam of a torus `map`. The boss is always visible.
+    pub fn view(
+        &self,
+        playfield: &Playfield,
+        map: &Map,
+        center: Vector2<f32>,
+        half: Vector2<f32>,
+    ) -> Playfield {
+        let rects = map.around(center, half);
+        let mut players: Vec<usize> = rects
+            .iter()
+            .flat_map(|rect| self.players.locate_in_envelope(rect))
+            .map(|p| p.data)
+            .collect();
+        let mut bullets: Vec<usize> = rects
+            .iter()
+            .flat_map(|rect| self.bullets.locate_in_envelope(rect))
+            .map(|b| b.data)
+            .collect();
+        // Keep the snapshot order so deltas and clients see a stable list,
+        // views wider than a torus see things more than once
+        players.sort_unstable();
+        players.dedup();
+        bullets.sort_unstable();
+        bullets.dedup();
+
+        Playfield {
+            seq: playfield.seq,
+            tick: playfield.tick,
+            input: playfield.input,
+            players: players
+                .into_iter()
+                .map(|i| playfield.players[i].clone())
+                .collect(),
+            bullets: bullets
+                .into_iter()
+                .map(|i| playfield.bullets[i].clone())
+                .collect(),
+            // Turrets are few, they don't need an index
+            turrets: playfield
+                .turrets
+                .iter()
+                .filter(|t| rects.iter().any(|r| r.contains_point(&[t.pos.x, t.pos.y])))
+                .cloned()
+                .collect(),
+            boss: playfield.boss.clone(),
+        }
+    }
+}
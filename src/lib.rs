@@ -0,0 +1,47 @@
+//! The s3 game server.
+//!
+//! Every arena is a `GameServer` actor running an `s3_sim::World`, clients
+//! talk to them through a `WsGameSession` on the `/ws` route. `main.rs` only
+//! reads the configuration and starts the arenas.
+use actix::Addr;
+use actix_files::Files;
+use actix_web::dev::Server;
+use actix_web::{middleware::Logger, web, App, HttpServer};
+use s3_sim::class::Classes;
+use std::io;
+use std::net::TcpListener;
+
+pub mod consts;
+pub mod ids;
+mod interest;
+pub mod protocol;
+pub mod server;
+pub mod session;
+
+use server::GameServer;
+use session::{game_route, HeartbeatTimeout, Resumes};
+
+/// Serve the client on `listener`, with `/ws` connecting to `homeserver`.
+/// Clients are told they can play `classes`, the ones the arenas have.
+pub fn serve(
+    listener: TcpListener,
+    homeserver: Addr<GameServer>,
+    heartbeat: HeartbeatTimeout,
+    classes: Classes,
+) -> io::Result<Server> {
+    let resumes = web::Data::new(Resumes::default());
+    let classes = web::Data::new(classes);
+    // Create Http server with WebSocket support
+    Ok(HttpServer::new(move || {
+        App::new()
+            .app_data(web::Data::new(homeserver.clone()))
+            .app_data(web::Data::new(heartbeat))
+            .app_data(resumes.clone())
+            .app_data(classes.clone())
+            .route("/ws", web::get().to(game_route))
+            .service(Files::new("/", "client/dist/").index_file("index.html"))
+            .wrap(Logger::default())
+    })
+    .listen(listener)?
+    .run())
+}
@@ -1,152 +1,197 @@
 use actix::*;
-use actix_files::Files;
-use actix_web::{middleware::Logger, web, App, Error, HttpRequest, HttpResponse, HttpServer};
-use actix_web_actors::ws;
 use na::Vector2;
 use nalgebra as na;
-use serde_json::json;
-
-mod boss;
-mod bullet;
-mod consts;
-mod player;
-mod server;
-
-use server::{
-    ClientMessage, Connect, DecodedMessage, Disconnect, GameServer, Message, TransferClient,
-};
-
-/// Entry point for our route
-async fn game_route(
-    req: HttpRequest,
-    stream: web::Payload,
-    srv: web::Data<Addr<server::GameServer>>,
-) -> Result<HttpResponse, Error> {
-    ws::start(
-        WsGameSession {
-            id: 0,
-            addr: srv.get_ref().clone(),
-        },
-        &req,
-        stream,
-    )
-}
-
-pub struct WsGameSession {
-    /// unique session id
-    id: usize,
-
-    addr: Addr<server::GameServer>,
+use std::fs;
+use std::io;
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use s3::consts;
+use s3::server::{self, GameServer};
+use s3::session::HeartbeatTimeout;
+use s3_sim::boss::Bosses;
+use s3_sim::bot::Difficulty;
+use s3_sim::class::Classes;
+use s3_sim::map::Map;
+use s3_sim::obstacle::Obstacle;
+
+/// Serve the client on `PORT`, with `/ws` connecting to `homeserver`
+async fn serve(
+    homeserver: Addr<GameServer>,
+    heartbeat: HeartbeatTimeout,
+    classes: Classes,
+) -> io::Result<()> {
+    let port = std::env::var("PORT").unwrap_or_else(|_| "8080".into());
+    println!("Starting a server on http://localhost:{}", port);
+    let listener = TcpListener::bind(format!("0.0.0.0:{}", port))?;
+    s3::serve(listener, homeserver, heartbeat, classes)?.await
 }
 
-impl Actor for WsGameSession {
-    type Context = ws::WebsocketContext<Self>;
-
-    /// Method is called on actor start.
-    /// We register ws session with GameServer
-    fn started(&mut self, ctx: &mut Self::Context) {
-        // register self in game server. `AsyncContext::wait` register
-        // future within context, but context waits until this future resolves
-        // before processing any other events.
-        // HttpContext::state() is instance of WsGameSessionState, state is shared across all
-        // routes within application
-        let addr: Addr<_> = ctx.address();
-        self.addr
-            .send(Connect { addr })
-            .into_actor(self)
-            .then(|res, act, ctx| {
-                match res {
-                    Ok(res) => {
-                        act.id = res;
-                        ctx.text(
-                            json!({
-                                "you": act.id,
-                                "pos": Vector2::new(400.0, 400.0),
-                            })
-                            .to_string(),
-                        );
-                    }
-                    // something is wrong with game server
-                    _ => ctx.stop(),
-                }
-                fut::ready(())
-            })
-            .wait(ctx);
-    }
-
-    fn stopping(&mut self, _: &mut Self::Context) -> Running {
-        // notify game server
-        self.addr.do_send(Disconnect { id: self.id });
-        Running::Stop
+/// `s3 replay FILE` re-simulates a recorded match and prints what happened,
+/// with `--serve` it is played back to clients instead
+async fn replay_command(args: &[String]) -> io::Result<()> {
+    let path = match args.first() {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: s3 replay FILE [--serve]");
+            return Ok(());
+        }
+    };
+    if args.iter().any(|a| a == "--serve") {
+        let heartbeat = HeartbeatTimeout(env_secs("HEARTBEAT_TIMEOUT", consts::HEARTBEAT_TIMEOUT));
+        let server = GameServer::replay(path)?;
+        let classes = server.classes().clone();
+        serve(server.start(), heartbeat, classes).await
+    } else {
+        s3_sim::replay::simulate(path)
     }
 }
 
-/// Handle messages from game server, we simply send it to peer WebSocket
-impl Handler<Message> for WsGameSession {
-    type Result = ();
-
-    fn handle(&mut self, msg: Message, ctx: &mut Self::Context) {
-        ctx.text(msg.0);
-    }
+/// A duration in seconds from the environment variable `name`
+fn env_secs(name: &str, default: Duration) -> Duration {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .map_or(default, Duration::from_secs_f64)
 }
 
-impl Handler<TransferClient> for WsGameSession {
-    type Result = ();
+#[actix_web::main]
+async fn main() -> io::Result<()> {
+    env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
 
-    fn handle(&mut self, msg: TransferClient, _: &mut Self::Context) {
-        self.addr = msg.0;
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("replay") {
+        return replay_command(&args[1..]).await;
     }
-}
 
-/// WebSocket message handler
-impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsGameSession {
-    fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
-        match msg {
-            Ok(ws::Message::Ping(msg)) => ctx.pong(&msg),
-            Ok(ws::Message::Pong(_)) => println!("Ping"),
-            Ok(ws::Message::Text(text)) => {
-                // All the client sends are key messages so we assume that the message is a key message
-                if let Ok(m) = serde_json::from_str::<ClientMessage>(text.trim()) {
-                    self.addr.do_send(DecodedMessage { id: self.id, m });
-                }
-
-                // send message to game server
-            }
-            Ok(ws::Message::Binary(_)) => println!("Unexpected binary"),
-            Ok(ws::Message::Close(_)) => {
-                ctx.stop();
-            }
-            _ => (),
+    // How much of the arena each client is sent, as `WIDTHxHEIGHT`
+    let view_size = std::env::var("VIEW_SIZE")
+        .ok()
+        .and_then(|v| {
+            let (w, h) = v.split_once('x')?;
+            Some(Vector2::new(w.parse().ok()?, h.parse().ok()?))
+        })
+        .unwrap_or_else(|| Vector2::new(consts::VIEW_SIZE[0], consts::VIEW_SIZE[1]));
+
+    let heartbeat = HeartbeatTimeout(env_secs("HEARTBEAT_TIMEOUT", consts::HEARTBEAT_TIMEOUT));
+    let afk_timeout = env_secs("AFK_TIMEOUT", consts::AFK_TIMEOUT);
+
+    // Bots keep the home arena at `MIN_PLAYERS`, at `BOT_DIFFICULTY`
+    let min_players = std::env::var("MIN_PLAYERS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(consts::MIN_PLAYERS);
+    let difficulty = std::env::var("BOT_DIFFICULTY")
+        .ok()
+        .and_then(|v| Difficulty::from_name(&v))
+        .unwrap_or(Difficulty::Normal);
+
+    // Classes come from the file at `CLASSES`, or are the built-in ones
+    let classes = match std::env::var("CLASSES") {
+        Ok(path) => Classes::load(&path).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("could not load classes from {}: {}", path, e),
+            )
+        })?,
+        Err(_) => Classes::default(),
+    };
+
+    // Bosses come from the file at `BOSSES`, or are the built-in ones. The
+    // boss arena fights the first of them, the hardcore arena the second.
+    let bosses = match std::env::var("BOSSES") {
+        Ok(path) => Bosses::load(&path).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("could not load bosses from {}: {}", path, e),
+            )
+        })?,
+        Err(_) => Bosses::default(),
+    };
+    let mut bosses = bosses.iter().cloned();
+    let (boss, hardcore_boss) = match (bosses.next(), bosses.next()) {
+        (Some(boss), Some(hardcore)) => (boss, hardcore),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "there need to be bosses for the boss and the hardcore arena",
+            ))
         }
-    }
-}
+    };
+
+    // Each arena is laid out by `NAME.json` in `MAPS` when there is one.
+    // Others are the default square, with obstacles generated when
+    // `OBSTACLES` is a seed or loaded when it is the path of a JSON file.
+    let obstacles = match std::env::var("OBSTACLES") {
+        Ok(v) => match v.parse() {
+            Ok(seed) => Obstacle::generate(seed, consts::OBSTACLES, Map::default().size),
+            Err(_) => Obstacle::load(&v).map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("could not load obstacles from {}: {}", v, e),
+                )
+            })?,
+        },
+        Err(_) => Vec::new(),
+    };
+    let map_dir = std::env::var("MAPS").ok().map(PathBuf::from);
+    let map = |name: &str| -> io::Result<Map> {
+        match map_dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{}.json", name)))
+        {
+            Some(path) if path.exists() => Map::load(&path).map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("could not load the map {}: {}", path.display(), e),
+                )
+            }),
+            _ => Ok(Map {
+                obstacles: obstacles.clone(),
+                ..Map::default()
+            }),
+        }
+    };
+    let (home_map, boss_map, hardcore_map) = (map("home")?, map("boss")?, map("hardcore")?);
 
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
+    // Every arena records a replay into `RECORD_DIR` when it is set
+    let record_dir = std::env::var("RECORD_DIR").ok().map(PathBuf::from);
+    if let Some(dir) = &record_dir {
+        fs::create_dir_all(dir)?;
+    }
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let start = |server: GameServer, name: &str| -> io::Result<Addr<GameServer>> {
+        let server = server
+            .with_view_size(view_size)
+            .with_afk_timeout(afk_timeout)
+            .with_classes(classes.clone());
+        let server = match &record_dir {
+            Some(dir) => server.record_to(dir.join(format!("{}-{}.replay", name, started)))?,
+            None => server,
+        };
+        Ok(server.start())
+    };
 
     // Start game server actor in separate thread
-    let homeserver = GameServer::new(None, None).start();
-
-    let bossserver =
-        GameServer::new(Some(boss::BossType::NormalBoss), Some(homeserver.clone())).start();
-    let bossserver2 =
-        GameServer::new(Some(boss::BossType::HardcoreBoss), Some(homeserver.clone())).start();
+    let homeserver = start(
+        GameServer::new(home_map, None, None).with_bots(min_players, difficulty),
+        "home",
+    )?;
+
+    let bossserver = start(
+        GameServer::new(boss_map, Some(boss), Some(homeserver.clone())),
+        "boss",
+    )?;
+    let bossserver2 = start(
+        GameServer::new(hardcore_map, Some(hardcore_boss), Some(homeserver.clone())),
+        "hardcore",
+    )?;
     // Create a wormhole to the new server
     homeserver.do_send(server::NewWormhole(bossserver.clone(), 1));
     homeserver.do_send(server::NewWormhole(bossserver2.clone(), 2));
 
-    let port = std::env::var("PORT").unwrap_or_else(|_| "8080".into());
-    println!("Starting a server on http://localhost:{}", port);
-    // Create Http server with WebSocket support
-    HttpServer::new(move || {
-        App::new()
-            .app_data(homeserver.clone())
-            .route("/ws", web::get().to(game_route))
-            .service(Files::new("/", "client/dist/").index_file("index.html"))
-            .wrap(Logger::default())
-    })
-    .bind(format!("0.0.0.0:{}", port))?
-    .run()
-    .await
+    serve(homeserver, heartbeat, classes).await
 }
@@ -0,0 +1,834 @@
+//! Wire types sent to clients, and the compact binary encoding of them.
+//!
+//! The first message of a client is a `ClientHello` with the protocol version
+//! it speaks and the encodings it can decode, in order of preference. The
+//! server answers with a `Welcome`, or closes the socket saying why it can't
+//! serve the client. Snapshots use the encoding picked there, everything
+//! else is JSON. All of it is a `ServerMessage`, encoded by each session.
+//!
+//! Binary frames start with the protocol version and a frame kind. Numbers are
+//! LEB128 varints (zigzag for signed values), positions and velocities are
+//! quantized to fixed point, and booleans are packed into flag bytes.
+//! Classes are sent as their position in the `classes` of the `Welcome`.
+//!
+//! Players hold the abilities of their class with `ClientMessage::Ability`,
+//! `Split` holds the first one. Snapshots carry the cooldown and remaining
+//! effect time of each ability slot, the `Welcome` says what they do.
+//! Freshly spawned players can't be hurt for a moment, snapshots say how much
+//! longer as `invulnerable`.
+//!
+//! Every snapshot carries a sequence number. Once a client acknowledges one
+//! with `ClientMessage::Ack`, later snapshots are sent as a `DeltaPlayfield`
+//! against it: only changed player fields, and bullets as spawn/despawn events
+//! since clients can extrapolate them from `pos` and `vel`. Bullets that
+//! turned or bounced since the base are spawned again with their new `vel`.
+//!
+//! For client-side prediction, inputs may carry a sequence number of their own
+//! (`{"seq": 7, "Target": [1, 0]}`). Snapshots carry the server tick and the
+//! last input the server applied for the client they are sent to, so it can
+//! drop the inputs that are accounted for and replay the rest on top.
+use crate::consts::*;
+use na::Vector2;
+use nalgebra as na;
+use s3_sim::ability::{Ability, Slot};
+use s3_sim::class::{Classes, Pattern};
+use s3_sim::map::Geometry;
+use s3_sim::obstacle::Obstacle;
+use s3_sim::{Input, World};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::f32::consts::PI;
+
+/// Bumped whenever the protocol changes
+pub const VERSION: u8 = 8;
+
+/// Frame kinds, the second byte of every binary frame
+pub const KIND_PLAYFIELD: u8 = 0;
+pub const KIND_DELTA: u8 = 1;
+
+/// Positions are sent in quarter units
+const POS_SCALE: f32 = 4.0;
+/// Velocities are sent in 1/64 units
+const VEL_SCALE: f32 = 64.0;
+
+const PLAYFIELD_BOSS: u8 = 1 << 0;
+const PLAYFIELD_BOSS_SHIELD: u8 = 1 << 1;
+
+/// Which fields a `PlayerDelta` carries, sent as a varint
+const DELTA_POS: u64 = 1 << 0;
+const DELTA_ANGLE: u64 = 1 << 1;
+const DELTA_HEALTH: u64 = 1 << 2;
+const DELTA_MANA: u64 = 1 << 3;
+const DELTA_SHOT_TIME: u64 = 1 << 4;
+const DELTA_NAME: u64 = 1 << 5;
+const DELTA_CLASS: u64 = 1 << 6;
+const DELTA_ABILITIES: u64 = 1 << 7;
+const DELTA_INVULNERABLE: u64 = 1 << 8;
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    Json,
+    Binary,
+}
+
+impl Encoding {
+    /// Encodings the server can send, in the order it prefers them
+    pub const ALL: [Encoding; 2] = [Encoding::Binary, Encoding::Json];
+
+    pub fn from_name(name: &str) -> Option<Encoding> {
+        match name {
+            "json" => Some(Encoding::Json),
+            "binary" => Some(Encoding::Binary),
+            _ => None,
+        }
+    }
+}
+
+/// Everything the server sends to clients. In JSON each is an object with the
+/// variant name as its only key, the binary encoding only covers snapshots.
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum ServerMessage {
+    Welcome(Welcome),
+    /// The client is in a new arena, of this size and shape
+    Arena(ClientArena),
+    Wormhole(ClientWormhole),
+    /// Everything in the arena that blocks players and bullets
+    Obstacles(Vec<Obstacle>),
+    /// The player with this id died or left
+    Death(usize),
+    /// The client moved to another arena, everything it knows is gone
+    Clear {},
+    Playfield(Playfield),
+    Delta(DeltaPlayfield),
+}
+
+/// The first message of every client
+#[derive(Deserialize)]
+pub enum ClientHello {
+    Hello {
+        /// Protocol version the client speaks, see `VERSION`
+        version: u8,
+        /// Encodings the client can decode, the first one the server knows is used
+        encodings: Vec<String>,
+        /// Token of a dropped session to take over
+        #[serde(default)]
+        resume: Option<String>,
+    },
+}
+
+/// What a class can do, for clients to show
+#[derive(Serialize, Clone)]
+pub struct ClassInfo {
+    pub class: String,
+    pub pattern: Pattern,
+    /// Milliseconds between volleys
+    pub shot_cooldown: u64,
+    /// Milliseconds a bullet flies for
+    pub bullet_lifetime: u64,
+    /// What its ability slots do
+    pub abilities: Vec<Ability>,
+}
+
+/// The server's answer to a `ClientHello`
+#[derive(Serialize, Clone)]
+pub struct Welcome {
+    pub version: u8,
+    /// Encoding snapshots are sent in
+    pub encoding: Encoding,
+    pub encodings: [Encoding; 2],
+    /// Version of the server build
+    pub server: &'static str,
+    /// Milliseconds per simulation step
+    pub tick: u64,
+    pub classes: Vec<ClassInfo>,
+    /// Id of the client's session, and of its player once it spawns
+    pub you: usize,
+    /// Secret to resume the session with if the connection drops
+    pub token: String,
+    /// Where the camera looks until the client spawns
+    pub pos: Vector2<f32>,
+}
+
+impl Welcome {
+    pub fn new(encoding: Encoding, you: usize, token: String, classes: &Classes) -> Welcome {
+        Welcome {
+            version: VERSION,
+            encoding,
+            encodings: Encoding::ALL,
+            server: env!("CARGO_PKG_VERSION"),
+            tick: TICK_MILLIS,
+            classes: classes
+                .iter()
+                .map(|class| ClassInfo {
+                    class: class.name.clone(),
+                    pattern: class.pattern,
+                    shot_cooldown: class.shot_cooldown() as u64 * TICK_MILLIS,
+                    bullet_lifetime: class.bullet_lifetime() as u64 * TICK_MILLIS,
+                    abilities: class.abilities.clone(),
+                })
+                .collect(),
+            you,
+            token,
+            pos: Vector2::new(LOBBY_POS[0], LOBBY_POS[1]),
+        }
+    }
+}
+
+/// Messages clients send
+#[derive(Deserialize)]
+pub enum ClientMessage {
+    Spawn(String, String),
+    Target(Vector2<f32>),
+    Click(bool),
+    /// Hold the first ability
+    Split(bool),
+    Join(bool),
+    Escape(bool),
+    /// Hold the ability in this slot
+    Ability(u8, bool),
+    /// The client has applied the snapshot with this sequence number
+    Ack(u32),
+}
+
+impl ClientMessage {
+    /// The input for the world, if this is one
+    pub fn into_input(self) -> Option<Input> {
+        Some(match self {
+            ClientMessage::Spawn(name, class) => Input::Spawn(name, class),
+            ClientMessage::Target(v) => Input::Target(v),
+            ClientMessage::Click(b) => Input::Click(b),
+            ClientMessage::Split(b) => Input::Split(b),
+            ClientMessage::Join(b) => Input::Join(b),
+            ClientMessage::Escape(b) => Input::Escape(b),
+            ClientMessage::Ability(slot, b) => Input::Ability(slot, b),
+            ClientMessage::Ack(_) => return None,
+        })
+    }
+}
+
+/// A `ClientMessage` as sent over the socket, with its input sequence number
+/// if the client numbers its inputs
+#[derive(Deserialize)]
+pub struct ClientInput {
+    #[serde(default)]
+    pub seq: Option<u32>,
+    #[serde(flatten)]
+    pub m: ClientMessage,
+}
+
+#[derive(Serialize, Clone)]
+pub struct ClientPlayer {
+    pub id: usize,
+    pub pos: Vector2<f32>,
+    pub name: String,
+    pub angle: f32,
+    pub health: u8,
+    pub mana: u8,
+    pub class: String,
+    /// What binary frames send for `class`
+    #[serde(skip)]
+    pub class_index: u8,
+    pub shot_time: u64,
+    pub abilities: Vec<ClientSlot>,
+    /// Milliseconds until the player can be hurt
+    pub invulnerable: u64,
+}
+/// An ability slot of a player
+#[derive(Serialize, Clone, PartialEq)]
+pub struct ClientSlot {
+    /// Milliseconds until it can be cast again
+    pub cooldown: u64,
+    /// Milliseconds its effect lasts for
+    pub active: u64,
+}
+impl ClientSlot {
+    fn new(slot: &Slot) -> ClientSlot {
+        ClientSlot {
+            cooldown: slot.cooldown as u64 * TICK_MILLIS,
+            active: slot.active as u64 * TICK_MILLIS,
+        }
+    }
+}
+#[derive(Serialize, Clone)]
+pub struct ClientBullet {
+    pub vel: Vector2<f32>,
+    pub pos: Vector2<f32>,
+    pub id: usize,
+}
+#[derive(Serialize, Clone)]
+pub struct ClientArena {
+    pub size: Vector2<f32>,
+    pub geometry: Geometry,
+}
+#[derive(Serialize, Clone)]
+pub struct ClientWormhole {
+    pub pos: Vector2<f32>,
+    pub color: u8,
+}
+#[derive(Serialize, Clone)]
+pub struct ClientTurret {
+    pub pos: Vector2<f32>,
+    pub owner: usize,
+}
+#[derive(Serialize, Clone)]
+pub struct ClientBoss {
+    pub pos: Vector2<f32>,
+    pub health: u8,
+    /// Whether the boss takes no damage right now
+    pub shield: bool,
+}
+#[derive(Serialize, Clone)]
+pub struct Playfield {
+    pub seq: u32,
+    /// Server tick the snapshot was taken after
+    pub tick: u64,
+    /// Last input the server applied for the client this is sent to
+    pub input: u32,
+    pub players: Vec<ClientPlayer>,
+    pub bullets: Vec<ClientBullet>,
+    pub turrets: Vec<ClientTurret>,
+    pub boss: Option<ClientBoss>,
+}
+
+/// A player in a `DeltaPlayfield`, with only the fields that changed
+#[derive(Serialize, Default, Clone)]
+pub struct PlayerDelta {
+    pub id: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pos: Option<Vector2<f32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub angle: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub health: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mana: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub class: Option<String>,
+    #[serde(skip)]
+    pub class_index: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shot_time: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub abilities: Option<Vec<ClientSlot>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub invulnerable: Option<u64>,
+}
+
+impl PlayerDelta {
+    fn is_empty(&self) -> bool {
+        self.pos.is_none()
+            && self.name.is_none()
+            && self.angle.is_none()
+            && self.health.is_none()
+            && self.mana.is_none()
+            && self.class.is_none()
+            && self.shot_time.is_none()
+            && self.abilities.is_none()
+            && self.invulnerable.is_none()
+    }
+}
+
+/// Changes since snapshot `base`
+#[derive(Serialize, Clone)]
+pub struct DeltaPlayfield {
+    pub seq: u32,
+    pub base: u32,
+    pub tick: u64,
+    pub input: u32,
+    pub players: Vec<PlayerDelta>,
+    pub removed: Vec<usize>,
+    pub spawned: Vec<ClientBullet>,
+    pub despawned: Vec<usize>,
+    /// All turrets, they are few
+    pub turrets: Vec<ClientTurret>,
+    pub boss: Option<ClientBoss>,
+}
+
+/// What a client was sent in one snapshot, used as the base of later deltas
+pub struct Baseline {
+    players: HashMap<usize, ClientPlayer>,
+    /// Velocities of the bullets, by id
+    bullets: HashMap<usize, Vector2<f32>>,
+}
+
+impl Baseline {
+    pub fn new(playfield: &Playfield) -> Baseline {
+        Baseline {
+            players: playfield
+                .players
+                .iter()
+                .map(|p| (p.id, p.clone()))
+                .collect(),
+            bullets: playfield.bullets.iter().map(|b| (b.id, b.vel)).collect(),
+        }
+    }
+}
+
+fn changed<T: PartialEq + Clone>(old: &T, new: &T) -> Option<T> {
+    if old == new {
+        None
+    } else {
+        Some(new.clone())
+    }
+}
+
+impl Playfield {
+    /// What clients get to see of the world
+    pub fn new(world: &World, seq: u32) -> Playfield {
+        Playfield {
+            seq,
+            tick: world.tick,
+            input: 0,
+            players: world
+                .players
+                .values()
+                .map(|p| ClientPlayer {
+                    id: p.id,
+                    pos: p.pos,
+                    angle: p.target.x.atan2(p.target.y),
+                    health: p.health,
+                    mana: p.mana,
+                    class: p.class.clone(),
+                    class_index: world.classes.index(&p.class).unwrap_or(0) as u8,
+                    name: (*p.name).to_string(),
+                    // Time since the last volley, clients only draw it up to the cooldown
+                    shot_time: world
+                        .classes
+                        .get_or_first(&p.class)
+                        .shot_cooldown()
+                        .saturating_sub(p.shot_cooldown) as u64
+                        * TICK_MILLIS,
+                    abilities: p.abilities.iter().map(ClientSlot::new).collect(),
+                    invulnerable: p.invulnerable as u64 * TICK_MILLIS,
+                })
+                .collect(),
+            bullets: world
+                .bullets
+                .iter()
+                .map(|b| ClientBullet {
+                    pos: b.pos,
+                    vel: b.vel,
+                    id: b.id,
+                })
+                .chain(world.boss_bullets.iter().map(|b| ClientBullet {
+                    pos: b.pos,
+                    vel: b.vel,
+                    id: b.id,
+                }))
+                .collect(),
+            turrets: world
+                .turrets
+                .iter()
+                .map(|t| ClientTurret {
+                    pos: t.pos,
+                    owner: t.owner,
+                })
+                .collect(),
+            boss: world.boss.as_ref().and_then(|b| {
+                if b.health > 0 {
+                    Some(ClientBoss {
+                        pos: b.pos,
+                        health: b.health,
+                        shield: b.shielded(),
+                    })
+                } else {
+                    None
+                }
+            }),
+        }
+    }
+
+    /// Everything that changed since `base`, which the client has as snapshot `base_seq`
+    pub fn delta(&self, base_seq: u32, base: &Baseline) -> DeltaPlayfield {
+        let mut players = Vec::new();
+        for p in &self.players {
+            let delta = match base.players.get(&p.id) {
+                Some(old) => PlayerDelta {
+                    id: p.id,
+                    pos: changed(&old.pos, &p.pos),
+                    name: changed(&old.name, &p.name),
+                    angle: changed(&old.angle, &p.angle),
+                    health: changed(&old.health, &p.health),
+                    mana: changed(&old.mana, &p.mana),
+                    class: changed(&old.class, &p.class),
+                    class_index: p.class_index,
+                    shot_time: changed(&old.shot_time, &p.shot_time),
+                    abilities: changed(&old.abilities, &p.abilities),
+                    invulnerable: changed(&old.invulnerable, &p.invulnerable),
+                },
+                None => PlayerDelta {
+                    id: p.id,
+                    pos: Some(p.pos),
+                    name: Some(p.name.clone()),
+                    angle: Some(p.angle),
+                    health: Some(p.health),
+                    mana: Some(p.mana),
+                    class: Some(p.class.clone()),
+                    class_index: p.class_index,
+                    shot_time: Some(p.shot_time),
+                    abilities: Some(p.abilities.clone()),
+                    invulnerable: Some(p.invulnerable),
+                },
+            };
+            if !delta.is_empty() {
+                players.push(delta);
+            }
+        }
+        let current: HashSet<usize> = self.players.iter().map(|p| p.id).collect();
+        let removed = base
+            .players
+            .keys()
+            .filter(|id| !current.contains(id))
+            .copied()
+            .collect();
+
+        let current: HashSet<usize> = self.bullets.iter().map(|b| b.id).collect();
+        let spawned = self
+            .bullets
+            .iter()
+            .filter(|b| base.bullets.get(&b.id) != Some(&b.vel))
+            .cloned()
+            .collect();
+        let despawned = base
+            .bullets
+            .keys()
+            .filter(|id| !current.contains(id))
+            .copied()
+            .collect();
+
+        DeltaPlayfield {
+            seq: self.seq,
+            base: base_seq,
+            tick: self.tick,
+            input: self.input,
+            players,
+            removed,
+            spawned,
+            despawned,
+            turrets: self.turrets.clone(),
+            boss: self.boss.clone(),
+        }
+    }
+
+    pub fn to_binary(&self) -> Vec<u8> {
+        let mut w = Writer::new(KIND_PLAYFIELD);
+        w.varint(self.seq as u64);
+        w.varint(self.tick);
+        w.varint(self.input as u64);
+        w.u8(boss_flags(&self.boss));
+
+        w.varint(self.players.len() as u64);
+        for p in &self.players {
+            w.varint(p.id as u64);
+            w.vector(p.pos, POS_SCALE);
+            w.angle(p.angle);
+            w.u8(p.health);
+            w.u8(p.mana);
+            w.u8(p.class_index);
+            w.varint(p.shot_time);
+            w.string(&p.name);
+            w.slots(&p.abilities);
+            w.varint(p.invulnerable);
+        }
+
+        w.varint(self.bullets.len() as u64);
+        for b in &self.bullets {
+            w.bullet(b);
+        }
+        w.turrets(&self.turrets);
+
+        if let Some(boss) = &self.boss {
+            w.boss(boss);
+        }
+        w.finish()
+    }
+}
+
+impl DeltaPlayfield {
+    pub fn to_binary(&self) -> Vec<u8> {
+        let mut w = Writer::new(KIND_DELTA);
+        w.varint(self.seq as u64);
+        w.varint(self.base as u64);
+        w.varint(self.tick);
+        w.varint(self.input as u64);
+        w.u8(boss_flags(&self.boss));
+
+        w.varint(self.players.len() as u64);
+        for p in &self.players {
+            let mut mask = 0;
+            for (present, bit) in [
+                (p.pos.is_some(), DELTA_POS),
+                (p.angle.is_some(), DELTA_ANGLE),
+                (p.health.is_some(), DELTA_HEALTH),
+                (p.mana.is_some(), DELTA_MANA),
+                (p.shot_time.is_some(), DELTA_SHOT_TIME),
+                (p.name.is_some(), DELTA_NAME),
+                (p.class.is_some(), DELTA_CLASS),
+                (p.abilities.is_some(), DELTA_ABILITIES),
+                (p.invulnerable.is_some(), DELTA_INVULNERABLE),
+            ] {
+                if present {
+                    mask |= bit;
+                }
+            }
+            w.varint(p.id as u64);
+            w.varint(mask);
+            if let Some(pos) = p.pos {
+                w.vector(pos, POS_SCALE);
+            }
+            if let Some(angle) = p.angle {
+                w.angle(angle);
+            }
+            if let Some(health) = p.health {
+                w.u8(health);
+            }
+            if let Some(mana) = p.mana {
+                w.u8(mana);
+            }
+            if let Some(shot_time) = p.shot_time {
+                w.varint(shot_time);
+            }
+            if let Some(name) = &p.name {
+                w.string(name);
+            }
+            if p.class.is_some() {
+                w.u8(p.class_index);
+            }
+            if let Some(abilities) = &p.abilities {
+                w.slots(abilities);
+            }
+            if let Some(invulnerable) = p.invulnerable {
+                w.varint(invulnerable);
+            }
+        }
+
+        w.varint(self.removed.len() as u64);
+        for id in &self.removed {
+            w.varint(*id as u64);
+        }
+        w.varint(self.spawned.len() as u64);
+        for b in &self.spawned {
+            w.bullet(b);
+        }
+        w.varint(self.despawned.len() as u64);
+        for id in &self.despawned {
+            w.varint(*id as u64);
+        }
+        w.turrets(&self.turrets);
+
+        if let Some(boss) = &self.boss {
+            w.boss(boss);
+        }
+        w.finish()
+    }
+}
+
+/// The flags byte of a snapshot with `boss`
+fn boss_flags(boss: &Option<ClientBoss>) -> u8 {
+    match boss {
+        Some(b) if b.shield => PLAYFIELD_BOSS | PLAYFIELD_BOSS_SHIELD,
+        Some(_) => PLAYFIELD_BOSS,
+        None => 0,
+    }
+}
+
+/// Appends binary values to a frame
+pub struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new(kind: u8) -> Writer {
+        Writer {
+            buf: vec![VERSION, kind],
+        }
+    }
+    pub fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+    pub fn u8(&mut self, v: u8) {
+        self.buf.push(v);
+    }
+    pub fn varint(&mut self, mut v: u64) {
+        while v >= 0x80 {
+            self.buf.push(v as u8 | 0x80);
+            v >>= 7;
+        }
+        self.buf.push(v as u8);
+    }
+    pub fn zigzag(&mut self, v: i64) {
+        self.varint(((v << 1) ^ (v >> 63)) as u64);
+    }
+    /// Fixed point vector, `scale` steps per unit
+    pub fn vector(&mut self, v: Vector2<f32>, scale: f32) {
+        self.zigzag((v.x * scale).round() as i64);
+        self.zigzag((v.y * scale).round() as i64);
+    }
+    /// Angle in radians, quantized to 16 bits over a full turn
+    pub fn angle(&mut self, a: f32) {
+        let turn = (a / (2.0 * PI)).rem_euclid(1.0);
+        let q = (turn * 65536.0) as u32 as u16;
+        self.buf.extend_from_slice(&q.to_le_bytes());
+    }
+    pub fn string(&mut self, s: &str) {
+        self.varint(s.len() as u64);
+        self.buf.extend_from_slice(s.as_bytes());
+    }
+    fn bullet(&mut self, b: &ClientBullet) {
+        self.varint(b.id as u64);
+        self.vector(b.pos, POS_SCALE);
+        self.vector(b.vel, VEL_SCALE);
+    }
+    fn slots(&mut self, slots: &[ClientSlot]) {
+        self.varint(slots.len() as u64);
+        for s in slots {
+            self.varint(s.cooldown);
+            self.varint(s.active);
+        }
+    }
+    fn turrets(&mut self, turrets: &[ClientTurret]) {
+        self.varint(turrets.len() as u64);
+        for t in turrets {
+            self.vector(t.pos, POS_SCALE);
+            self.varint(t.owner as u64);
+        }
+    }
+    fn boss(&mut self, b: &ClientBoss) {
+        self.vector(b.pos, POS_SCALE);
+        self.u8(b.health);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(f: impl FnOnce(&mut Writer)) -> Vec<u8> {
+        let mut w = Writer::new(KIND_PLAYFIELD);
+        f(&mut w);
+        w.finish()[2..].to_vec()
+    }
+
+    #[test]
+    fn varints_take_seven_bits_a_byte() {
+        assert_eq!(bytes(|w| w.varint(0)), [0]);
+        assert_eq!(bytes(|w| w.varint(127)), [0x7f]);
+        assert_eq!(bytes(|w| w.varint(128)), [0x80, 0x01]);
+        assert_eq!(bytes(|w| w.varint(300)), [0xac, 0x02]);
+        assert_eq!(
+            bytes(|w| w.varint(u64::MAX)),
+            [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]
+        );
+    }
+
+    #[test]
+    fn zigzag_interleaves_signs() {
+        assert_eq!(bytes(|w| w.zigzag(0)), [0]);
+        assert_eq!(bytes(|w| w.zigzag(-1)), [1]);
+        assert_eq!(bytes(|w| w.zigzag(1)), [2]);
+        assert_eq!(bytes(|w| w.zigzag(-64)), [0x7f]);
+        assert_eq!(bytes(|w| w.zigzag(64)), [0x80, 0x01]);
+    }
+
+    #[test]
+    fn angles_are_fractions_of_a_turn() {
+        assert_eq!(bytes(|w| w.angle(0.0)), [0x00, 0x00]);
+        assert_eq!(bytes(|w| w.angle(PI / 2.0)), [0x00, 0x40]);
+        assert_eq!(bytes(|w| w.angle(-PI / 2.0)), [0x00, 0xc0]);
+    }
+
+    fn playfield() -> Playfield {
+        Playfield {
+            seq: 5,
+            tick: 300,
+            input: 2,
+            players: vec![ClientPlayer {
+                id: 7,
+                pos: Vector2::new(1.0, -0.5),
+                name: "al".to_owned(),
+                angle: 0.0,
+                health: 200,
+                mana: 3,
+                class: "Sniper".to_owned(),
+                class_index: 1,
+                shot_time: 1000,
+                abilities: vec![ClientSlot {
+                    cooldown: 0,
+                    active: 16,
+                }],
+                invulnerable: 0,
+            }],
+            bullets: vec![ClientBullet {
+                id: 9,
+                pos: Vector2::new(2.0, 0.25),
+                vel: Vector2::new(-1.0, 0.5),
+            }],
+            turrets: Vec::new(),
+            boss: Some(ClientBoss {
+                pos: Vector2::new(0.0, 1.0),
+                health: 100,
+                shield: true,
+            }),
+        }
+    }
+
+    #[test]
+    fn snapshots_encode_to_known_bytes() {
+        #[rustfmt::skip]
+        let expected = [
+            VERSION, KIND_PLAYFIELD,
+            5, 0xac, 0x02, 2, PLAYFIELD_BOSS | PLAYFIELD_BOSS_SHIELD,
+            // One player
+            1, 7, 8, 3, 0, 0, 200, 3, 1, 0xe8, 0x07, 2, b'a', b'l', 1, 0, 16, 0,
+            // One bullet
+            1, 9, 16, 2, 0x7f, 64,
+            // No turrets, then the boss
+            0, 0, 8, 100,
+        ];
+        assert_eq!(playfield().to_binary(), expected);
+    }
+
+    #[test]
+    fn deltas_encode_only_what_changed() {
+        let base = playfield();
+        let mut next = playfield();
+        next.seq = 6;
+        next.tick = 301;
+        next.players[0].health = 190;
+        next.bullets.clear();
+        next.boss = None;
+        let delta = next.delta(base.seq, &Baseline::new(&base));
+
+        #[rustfmt::skip]
+        let expected = [
+            VERSION, KIND_DELTA,
+            6, 5, 0xad, 0x02, 2, 0,
+            // Player 7 lost health
+            1, 7, DELTA_HEALTH as u8, 190,
+            // Nobody removed or spawned, bullet 9 despawned, no turrets
+            0, 0, 1, 9, 0,
+        ];
+        assert_eq!(delta.to_binary(), expected);
+    }
+
+    #[test]
+    fn deltas_spawn_bullets_again_once_they_turn() {
+        let base = playfield();
+        let mut next = playfield();
+        next.seq = 6;
+        next.bullets[0].pos.x -= 1.0;
+        let moved = next.delta(base.seq, &Baseline::new(&base));
+        assert!(moved.spawned.is_empty() && moved.despawned.is_empty());
+
+        // Bounced off a wall
+        next.bullets[0].vel.x = 1.0;
+        let turned = next.delta(base.seq, &Baseline::new(&base));
+        assert_eq!(turned.spawned.len(), 1);
+        assert_eq!(turned.spawned[0].vel, Vector2::new(1.0, 0.5));
+        assert!(turned.despawned.is_empty());
+    }
+}
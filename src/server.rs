@@ -1,21 +1,27 @@
 //! `GameServer` is an actor. It maintains list of connection client session.
 //!  Peers send messages to other peers through `GameServer`.
-use crate::WsGameSession;
+use crate::session::WsGameSession;
 use actix::prelude::*;
 use na::Vector2;
 use nalgebra as na;
-use rand::prelude::*;
-use rstar::{RTree, RTreeObject, AABB};
-use serde_derive::{Deserialize, Serialize};
-use serde_json::json;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
-use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::io;
+use std::path::Path;
 use std::time::{Duration, Instant};
 
-use crate::boss::*;
-use crate::bullet::*;
 use crate::consts::*;
-use crate::player::*;
+use crate::ids::{next_id, skip_past};
+use crate::interest::Interest;
+use crate::protocol::*;
+use s3_sim::boss::*;
+use s3_sim::bot::*;
+use s3_sim::class::Classes;
+use s3_sim::map::Map;
+use s3_sim::player::*;
+use s3_sim::replay::*;
+use s3_sim::world::*;
 
 /// New game session is created
 #[derive(Message)]
@@ -28,6 +34,8 @@ pub struct Connect {
 #[rtype(result = "()")]
 pub struct DecodedMessage {
     pub id: usize,
+    /// Input sequence number, if the client numbers its inputs
+    pub seq: Option<u32>,
     pub m: ClientMessage,
 }
 
@@ -38,412 +46,409 @@ pub struct Disconnect {
     pub id: usize,
 }
 
+/// A dropped session is back, with a new connection
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct TransferClient(pub Addr<GameServer>);
+pub struct Resume {
+    pub id: usize,
+    pub addr: Addr<WsGameSession>,
+}
 
+/// The round trip to a session's client was measured
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct Message(pub String);
+pub struct Latency {
+    pub id: usize,
+    pub rtt: Duration,
+}
 
 #[derive(Message)]
 #[rtype(result = "()")]
-struct Transfer(usize, Addr<WsGameSession>, Player);
+pub struct TransferClient(pub Addr<GameServer>);
 
+/// Something for a session to encode and send to its client
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct NewWormhole(pub Addr<GameServer>, pub u8);
+pub struct Message(pub ServerMessage);
 
-#[derive(Deserialize)]
-pub enum ClientMessage {
-    Spawn(String, Classes),
-    Target(Vector2<f32>),
-    Click(bool),
-    Split(bool),
-    Join(bool),
-    Escape(bool),
-}
+#[derive(Message)]
+#[rtype(result = "()")]
+struct Transfer(usize, Session, Player);
 
-#[derive(Serialize)]
-struct ClientPlayer {
-    id: usize,
-    pos: Vector2<f32>,
-    name: String,
-    angle: f32,
-    health: u8,
-    mana: u8,
-    class: Classes,
-    shot_time: u128,
-}
-#[derive(Serialize)]
-struct ClientBullet {
-    vel: Vector2<f32>,
-    pos: Vector2<f32>,
-    id: usize,
-}
-#[derive(Serialize)]
-struct ClientWormhole {
-    pos: Vector2<f32>,
-    color: u8,
-}
-#[derive(Serialize)]
-struct ClientBoss {
-    pos: Vector2<f32>,
-    health: u8,
-}
-#[derive(Serialize)]
-struct Playfield {
-    players: Vec<ClientPlayer>,
-    bullets: Vec<ClientBullet>,
-    boss: Option<ClientBoss>,
-}
+/// A bot going through a wormhole
+#[derive(Message)]
+#[rtype(result = "()")]
+struct TransferBot(Bot, Player);
 
-struct Wormhole {
-    pos: Vector2<f32>,
-    color: u8,
-    addr: Addr<GameServer>,
-}
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct NewWormhole(pub Addr<GameServer>, pub u8);
 
-impl Wormhole {
-    const RADIUS: f32 = 30.0;
+/// How many snapshots a session may lag behind its last ack before it gets a
+/// full snapshot again
+const MAX_DELTA_AGE: usize = 32;
+
+/// A connected client and the snapshots it was sent
+struct Session {
+    addr: Addr<WsGameSession>,
+    /// Middle of the area the client is sent, follows its player while alive
+    center: Vector2<f32>,
+    /// Last snapshot the client acknowledged, deltas are sent against it
+    acked: Option<(u32, Baseline)>,
+    /// Snapshots sent since then, oldest first
+    unacked: VecDeque<(u32, Baseline)>,
+    /// Sequence number of the last input applied to the world
+    last_input: u32,
+    /// When the client last sent an input
+    last_active: Instant,
 }
 
-impl<'a> RTreeObject for &'a Wormhole {
-    type Envelope = AABB<[f32; 2]>;
-
-    fn envelope(&self) -> Self::Envelope {
-        let size = Wormhole::RADIUS;
-        AABB::from_corners(
-            [self.pos.x - size, self.pos.y - size],
-            [self.pos.x + size, self.pos.y + size],
-        )
+impl Session {
+    fn new(addr: Addr<WsGameSession>) -> Session {
+        Session {
+            addr,
+            center: Vector2::new(LOBBY_POS[0], LOBBY_POS[1]),
+            acked: None,
+            unacked: VecDeque::new(),
+            last_input: 0,
+            last_active: Instant::now(),
+        }
+    }
+    fn ack(&mut self, seq: u32) {
+        if let Some(i) = self.unacked.iter().position(|(s, _)| *s == seq) {
+            self.acked = self.unacked.drain(..=i).next_back();
+        }
     }
 }
 
 /// `GameServer` responsible for coordinating game sessions.
 /// implementation is super primitive
 pub struct GameServer {
-    sessions: HashMap<usize, Addr<WsGameSession>>,
-    players: HashMap<usize, Player>,
-    bullets: Vec<Bullet>,
-    boss: Option<Boss>,
-    boss_bullets: Vec<BossBullet>,
-    rng: ThreadRng,
-    wormholes: Vec<Wormhole>,
-    tick: Instant,
-    health_tick: Instant,
-    quickshot_mana: Instant,
-    sniper_mana: Instant,
-    boss_dead: Instant,
-    pvp_enabled: bool,
+    sessions: HashMap<usize, Session>,
+    world: World,
+    /// Where each of `world.wormholes` leads
+    wormholes: Vec<Addr<GameServer>>,
+    /// When the next simulation step is due
+    next_step: Instant,
     home_server: Option<Addr<GameServer>>,
+    /// Sequence number of the last snapshot
+    seq: u32,
+    /// Size of the area around its player each session is sent
+    view_size: Vector2<f32>,
+    /// Where accepted inputs are written, if this match is recorded
+    recorder: Option<Recorder>,
+    /// The recording being played back, clients only watch
+    playback: Option<Replay>,
+    /// How long a player may go without input before it is taken out
+    afk_timeout: Duration,
+    /// Everything that changes the world from outside since the last step,
+    /// applied in the order it came in like a replay does
+    queued: Vec<Record>,
+    /// Dropped sessions and their players, kept until the grace window runs
+    /// out
+    parked: HashMap<usize, (Session, Option<Player>, Instant)>,
+    /// Players run by the server itself
+    bots: BTreeMap<usize, Bot>,
+    /// Bots are added while there are fewer clients and bots than this, and
+    /// retired while there are more
+    min_players: usize,
+    bot_difficulty: Difficulty,
 }
 
+/// How many steps a late timer may catch up on before the backlog is dropped
+const MAX_CATCH_UP: u32 = 4;
+
 impl GameServer {
-    pub fn new(boss: Option<BossType>, home_server: Option<Addr<GameServer>>) -> GameServer {
-        let mut rng = rand::thread_rng();
+    /// An arena laid out like `map`, with a `boss` in it if any. Players
+    /// can escape to `home_server` from it.
+    pub fn new(
+        map: Map,
+        boss: Option<BossClass>,
+        home_server: Option<Addr<GameServer>>,
+    ) -> GameServer {
         GameServer {
             sessions: HashMap::new(),
-            players: HashMap::new(),
-            bullets: Vec::new(),
+            world: World::new(rand::random(), map, boss, home_server.is_some()),
             wormholes: Vec::new(),
-            boss: if let Some(boss_type) = boss {
-                Some(Boss {
-                    pos: Vector2::new(rng.gen_range(0.0..WORLDSIZE), rng.gen_range(0.0..WORLDSIZE)),
-                    vel: Vector2::new(0.0, 0.0),
-                    health: 255,
-                    shot_time: Instant::now(),
-                    shot_time2: Instant::now(),
-                    class: boss_type,
-                })
-            } else {
-                None
-            },
-            pvp_enabled: true,
-            boss_bullets: Vec::new(),
-            rng,
-            tick: Instant::now(),
-            health_tick: Instant::now(),
-            quickshot_mana: Instant::now(),
-            sniper_mana: Instant::now(),
-            boss_dead: Instant::now(),
+            next_step: Instant::now(),
             home_server,
+            seq: 0,
+            view_size: Vector2::new(VIEW_SIZE[0], VIEW_SIZE[1]),
+            recorder: None,
+            playback: None,
+            afk_timeout: AFK_TIMEOUT,
+            queued: Vec::new(),
+            parked: HashMap::new(),
+            bots: BTreeMap::new(),
+            min_players: 0,
+            bot_difficulty: Difficulty::Normal,
         }
     }
-    /// Send message to all players
-    fn send_message(&self, message: &str) {
-        for addr in self.sessions.values() {
-            addr.do_send(Message(message.to_owned()));
+    /// A server playing back a recorded match to anyone who connects
+    pub fn replay(path: impl AsRef<Path>) -> io::Result<GameServer> {
+        let playback = Replay::open(path)?;
+        // Spectators must not share an id with a recorded player, clients
+        // would take that player for their own
+        if let Some(last) = playback
+            .entries
+            .iter()
+            .filter_map(|e| e.record.player())
+            .max()
+        {
+            skip_past(last);
         }
+        let mut server = GameServer::new(Map::default(), None, None);
+        server.world = playback.header.world();
+        server.playback = Some(playback);
+        // Spectators have no player to follow, show them everything
+        server.view_size = 2.0 * server.world.map.size;
+        Ok(server)
     }
-    fn tick(&self, ctx: &mut Context<Self>) {
-        ctx.run_later(Duration::from_millis(16), |act, ctx| {
-            act.move_and_things();
-
-            act.send_to_players();
-
-            act.tick(ctx);
-        });
+    /// Record the match to a replay file
+    pub fn record_to(mut self, path: impl AsRef<Path>) -> io::Result<GameServer> {
+        let header = Header {
+            seed: self.world.seed,
+            boss: self.world.boss.as_ref().map(|b| b.class.clone()),
+            escapable: self.world.escapable,
+            classes: self.world.classes.clone(),
+            map: self.world.map.clone(),
+        };
+        self.recorder = Some(Recorder::create(path, &header)?);
+        Ok(self)
     }
-    fn move_and_things(&mut self) {
-        let dt = self.tick.elapsed().as_millis() as f32 / 16.0;
-        let ht = (self.health_tick.elapsed().as_millis() / 48) as u8;
-        let qt = (self.quickshot_mana.elapsed().as_millis() / 24) as u8; // 2/3*1/16 of millis
-        let st = (self.sniper_mana.elapsed().as_millis() / 16) as u8;
-        if let Some(boss) = &mut self.boss {
-            if boss.health > 0 {
-                if !self.players.is_empty() {
-                    boss.tick(
-                        dt,
-                        &mut self.rng,
-                        &mut self.boss_bullets,
-                        self.players.values().peekable(),
-                    );
-                }
-            } else if self.boss_dead.elapsed() > Duration::from_millis(3000) {
-                boss.pos = Vector2::new(
-                    self.rng.gen_range(0.0..WORLDSIZE),
-                    self.rng.gen_range(0.0..WORLDSIZE),
-                );
-                boss.vel = Vector2::new(0.0, 0.0);
-                boss.health = 255;
-                boss.shot_time = Instant::now();
-                boss.shot_time2 = Instant::now();
+    fn record(&mut self, record: Record) {
+        if let Some(recorder) = &mut self.recorder {
+            if let Err(e) = recorder.record(self.world.tick, record) {
+                println!("Stopped recording: {}", e);
+                self.recorder = None;
             }
         }
-        for p in self.players.values_mut() {
-            p.tick(dt, &mut self.rng, &mut self.bullets);
-
-            p.health = p.health.saturating_add(ht);
-
-            p.mana = p.mana.saturating_add(match p.class {
-                Classes::Quickshot => qt,
-                Classes::Sniper => st,
-            });
+    }
+    /// Record a change to the world and apply it before the next step
+    fn queue(&mut self, record: Record) {
+        self.record(record.clone());
+        self.queued.push(record);
+    }
+    /// Apply the queued changes, telling clients about new wormholes
+    fn apply_queued(&mut self) {
+        let wormholes = self.world.wormholes.len();
+        for record in std::mem::take(&mut self.queued) {
+            record.apply(&mut self.world);
         }
-
-        self.health_tick += Duration::from_millis(ht as u64 * 48);
-        self.quickshot_mana += Duration::from_millis(qt as u64 * 24);
-        self.sniper_mana += Duration::from_millis(st as u64 * 16);
-
-        for b in self.bullets.iter_mut() {
-            b.pos += b.vel * dt;
+        for w in &self.world.wormholes[wormholes..] {
+            self.send_message(ServerMessage::Wormhole(ClientWormhole {
+                pos: w.pos,
+                color: w.color,
+            }));
         }
-        for b in self.boss_bullets.iter_mut() {
-            b.pos += b.vel * dt;
+    }
+    /// Change how far around their player sessions can see
+    pub fn with_view_size(mut self, view_size: Vector2<f32>) -> GameServer {
+        self.view_size = view_size;
+        self
+    }
+    /// Change how long players may go without input
+    pub fn with_afk_timeout(mut self, afk_timeout: Duration) -> GameServer {
+        self.afk_timeout = afk_timeout;
+        self
+    }
+    /// Let players pick from `classes` instead of the built-in ones
+    pub fn with_classes(mut self, classes: Classes) -> GameServer {
+        self.world.classes = classes;
+        self
+    }
+    /// The classes players can pick from
+    pub fn classes(&self) -> &Classes {
+        &self.world.classes
+    }
+    /// Keep the arena topped up to `min_players` with bots of `difficulty`
+    pub fn with_bots(mut self, min_players: usize, difficulty: Difficulty) -> GameServer {
+        self.min_players = min_players;
+        self.bot_difficulty = difficulty;
+        self
+    }
+    /// Send message to all players
+    fn send_message(&self, message: ServerMessage) {
+        for s in self.sessions.values() {
+            s.addr.do_send(Message(message.clone()));
         }
-
-        self.collision_trees();
-
-        self.reap_players();
-
-        self.escape_players();
-
-        self.bullets.retain(|b| {
-            b.spawn.elapsed()
-                < Duration::from_millis(match b.class {
-                    Classes::Quickshot => 600,
-                    _ => 1000,
-                })
+    }
+    fn tick(&self, ctx: &mut Context<Self>) {
+        ctx.run_interval(TICK, |act, _| {
+            if act.step() {
+                act.send_to_players();
+            }
         });
-
-        self.boss_bullets
-            .retain(|b| b.spawn.elapsed() < Duration::from_millis(1250));
-
-        self.tick = Instant::now();
-    }
-    fn send_to_players(&self) {
-        let playfield = Playfield {
-            players: self
-                .players
-                .iter()
-                .map(|(_, p)| ClientPlayer {
-                    id: p.id,
-                    pos: p.pos,
-                    angle: p.target.x.atan2(p.target.y),
-                    health: p.health,
-                    mana: p.mana,
-                    class: p.class,
-                    name: (*p.name).to_string(),
-                    shot_time: p.shot_time.elapsed().as_millis(),
-                })
-                .collect(),
-            bullets: self
-                .bullets
-                .iter()
-                .map(|b| ClientBullet {
-                    pos: b.pos,
-                    vel: b.vel,
-                    id: b.id,
-                })
-                .chain(self.boss_bullets.iter().map(|b| ClientBullet {
-                    pos: b.pos,
-                    vel: b.vel,
-                    id: b.id,
-                }))
-                .collect(),
-            boss: self.boss.as_ref().and_then(|b| {
-                if b.health > 0 {
-                    Some(ClientBoss {
-                        pos: b.pos,
-                        health: b.health,
-                    })
-                } else {
-                    None
-                }
-            }),
-        };
-        let serialized = ::serde_json::to_string(&playfield).unwrap();
-        self.send_message(&serialized);
-    }
-    fn escape_players(&mut self) {
-        let mut escapers = Vec::new();
-        for (i, p) in self.players.iter() {
-            if let Some(time) = p.escape_time {
-                if time.elapsed() > Duration::from_secs(1) {
-                    escapers.push(*i)
+    }
+    /// Run the steps that are due, returns whether there were any
+    fn step(&mut self) -> bool {
+        let now = Instant::now();
+        let mut steps = 0;
+        while self.next_step <= now {
+            if steps == MAX_CATCH_UP {
+                // Too far behind, slow the game down rather than stall
+                self.next_step = now + TICK;
+                break;
+            }
+            if let Some(playback) = &mut self.playback {
+                if !playback.advance(&mut self.world) {
+                    // The recording is over, freeze on the last frame
+                    self.next_step = now + TICK;
+                    break;
                 }
             }
+            if self.playback.is_none() {
+                self.think_bots();
+                self.apply_queued();
+            }
+            let events = self.world.step(Vec::new());
+            self.handle_events(events);
+            self.next_step += TICK;
+            steps += 1;
         }
-        if let Some(hs) = &self.home_server {
-            for escaper in escapers {
-                if let Some(p) = self.players.remove(&escaper) {
-                    if let Some(a) = self.sessions.remove(&escaper) {
-                        a.do_send(TransferClient(hs.clone()));
-                        self.send_message(
-                            &json!({
-                                "death": p.id,
-                            })
-                            .to_string(),
-                        );
-                        hs.do_send(Transfer(p.id, a, p));
-                    }
-                }
+        if self.playback.is_none() {
+            self.kick_idle();
+            self.populate();
+        }
+        self.parked
+            .retain(|_, (_, _, since)| since.elapsed() < RESUME_GRACE);
+        if let Some(recorder) = &mut self.recorder {
+            if let Err(e) = recorder.flush() {
+                println!("Stopped recording: {}", e);
+                self.recorder = None;
             }
         }
+        steps > 0
     }
-    fn collision_trees(&mut self) {
-        let pt = RTree::bulk_load(self.players.values().collect());
-
-        let mut move_players = Vec::new();
-        for w in &self.wormholes {
-            let mut wv = Vec::new();
-            let intersecting = pt.locate_in_envelope_intersecting(&(w).envelope());
-            for intersect in intersecting {
-                if intersect.join
-                    && (intersect.pos - w.pos).magnitude()
-                        <= (Player::RADIUS + Wormhole::RADIUS).powf(2.0)
-                {
-                    wv.push(intersect.id);
-                }
-            }
-            move_players.push(wv);
+    /// Tell a client what the arena looks like and where the wormholes are
+    fn send_arena(&self, addr: &Addr<WsGameSession>) {
+        let map = &self.world.map;
+        addr.do_send(Message(ServerMessage::Arena(ClientArena {
+            size: map.size,
+            geometry: map.geometry,
+        })));
+        if !map.obstacles.is_empty() {
+            addr.do_send(Message(ServerMessage::Obstacles(map.obstacles.clone())));
         }
-        for (i, pl) in move_players.iter().enumerate() {
-            for pi in pl {
-                if let Some(p) = self.players.remove(pi) {
-                    if let Some(a) = self.sessions.remove(pi) {
-                        a.do_send(TransferClient(self.wormholes[i].addr.clone()));
-                        self.send_message(
-                            &json!({
-                                "death": p.id,
-                            })
-                            .to_string(),
-                        );
-                        self.wormholes[i].addr.do_send(Transfer(p.id, a, p));
-                    }
+        for w in &self.world.wormholes {
+            addr.do_send(Message(ServerMessage::Wormhole(ClientWormhole {
+                pos: w.pos,
+                color: w.color,
+            })));
+        }
+    }
+    /// Take out the players whose client has not sent input for too long,
+    /// their session stays and goes back to the lobby
+    fn kick_idle(&mut self) {
+        let (afk_timeout, players) = (self.afk_timeout, &self.world.players);
+        let idle: Vec<usize> = self
+            .sessions
+            .iter_mut()
+            .filter(|(id, s)| s.last_active.elapsed() > afk_timeout && players.contains_key(id))
+            .map(|(id, s)| {
+                // The kick lands with the next step, don't kick again until then
+                s.last_active = Instant::now();
+                *id
+            })
+            .collect();
+        for id in idle {
+            self.queue(Record::Kick(id));
+            self.send_message(ServerMessage::Death(id));
+        }
+    }
+    /// Queue the inputs of all bots for the next step
+    fn think_bots(&mut self) {
+        let senses = Senses::new(&self.world);
+        let inputs: Vec<(usize, Input)> = self
+            .bots
+            .values_mut()
+            .flat_map(|bot| {
+                let id = bot.id;
+                bot.think(&senses).into_iter().map(move |input| (id, input))
+            })
+            .collect();
+        for (id, input) in inputs {
+            self.queue(Record::Input(id, input));
+        }
+    }
+    /// Add or retire a bot if the arena is not at its minimum population
+    fn populate(&mut self) {
+        if self.min_players == 0 {
+            return;
+        }
+        let population = self.sessions.len() + self.bots.len();
+        if population < self.min_players {
+            let id = next_id();
+            let bot = Bot::new(id, self.bot_difficulty, &self.world, rand::random());
+            self.bots.insert(id, bot);
+        } else if population > self.min_players {
+            if let Some((id, _)) = self.bots.pop_last() {
+                if self.world.players.contains_key(&id) {
+                    self.queue(Record::Leave(id));
+                    self.send_message(ServerMessage::Death(id));
                 }
             }
         }
-
-        let dt = RTree::bulk_load(self.bullets.iter().map(|b| b).collect());
-        let dbt = RTree::bulk_load(self.boss_bullets.iter().map(|b| b).collect());
-
-        let mut health_map = HashMap::new();
-        let mut health_add = HashMap::new();
-        let mut delete_bullets = HashSet::new();
-        let mut delete_boss_bullets = HashSet::new();
-        if let Some(boss) = &mut self.boss {
-            if boss.health > 0 {
-                let intersecting = dt.locate_in_envelope_intersecting(&(&*boss).envelope());
-                for intersect in intersecting {
-                    if (intersect.pos - boss.pos).magnitude()
-                        <= (Boss::RADIUS + Bullet::RADIUS).powf(2.0)
-                    {
-                        boss.health = boss.health.saturating_sub(8);
-                        *health_add.entry(intersect.owner).or_insert(0) += 4;
-
-                        delete_bullets.insert(intersect.id);
+    }
+    fn handle_events(&mut self, events: Vec<Event>) {
+        for event in events {
+            let (p, to) = match event {
+                Event::Death(id) => {
+                    self.send_message(ServerMessage::Death(id));
+                    if self.home_server.is_some() {
+                        // Bots that die away from home are replaced there
+                        self.bots.remove(&id);
                     }
+                    continue;
                 }
-                if boss.health == 0 {
-                    self.boss_dead = Instant::now();
-                }
+                Event::Wormhole(i, p) => match self.wormholes.get(i) {
+                    Some(to) => (p, to.clone()),
+                    None => continue,
+                },
+                Event::Escape(p) => match &self.home_server {
+                    Some(hs) => (p, hs.clone()),
+                    None => continue,
+                },
+            };
+            if let Some(a) = self.sessions.remove(&p.id) {
+                a.addr.do_send(TransferClient(to.clone()));
+                self.send_message(ServerMessage::Death(p.id));
+                to.do_send(Transfer(p.id, a, p));
+            } else if let Some(bot) = self.bots.remove(&p.id) {
+                self.send_message(ServerMessage::Death(p.id));
+                to.do_send(TransferBot(bot, p));
             }
         }
-        for (i, p) in &self.players {
-            if self.pvp_enabled {
-                let intersecting = dt.locate_in_envelope_intersecting(&(p).envelope());
-                for intersect in intersecting {
-                    if intersect.owner != p.id
-                        && (intersect.pos - p.pos).magnitude()
-                            <= (Player::RADIUS + Bullet::RADIUS).powf(2.0)
-                    {
-                        *health_map.entry(*i).or_insert(0) += 8;
-                        *health_add.entry(intersect.owner).or_insert(0) += 4;
-
-                        delete_bullets.insert(intersect.id);
-                    }
-                }
+    }
+    fn send_to_players(&mut self) {
+        self.seq = self.seq.wrapping_add(1);
+        let playfield = Playfield::new(&self.world, self.seq);
+        let interest = Interest::new(&playfield);
+
+        for (id, s) in self.sessions.iter_mut() {
+            if let Some(p) = self.world.players.get(id) {
+                s.center = p.pos;
             }
-            let intersecting = dbt.locate_in_envelope_intersecting(&(p).envelope());
-            for intersect in intersecting {
-                if (intersect.pos - p.pos).magnitude()
-                    <= (Player::RADIUS + BossBullet::RADIUS).powf(2.0)
-                {
-                    if let Some(boss) = &mut self.boss {
-                        if boss.health > 0 {
-                            boss.health = boss.health.saturating_add(20);
-                        }
-                    }
-                    *health_map.entry(*i).or_insert(0) += 50;
-
-                    delete_boss_bullets.insert(intersect.id);
+            let mut view =
+                interest.view(&playfield, &self.world.map, s.center, self.view_size / 2.0);
+            view.input = s.last_input;
+
+            if let Some((base, _)) = &s.acked {
+                if self.seq.wrapping_sub(*base) as usize > MAX_DELTA_AGE {
+                    // Too far behind, resync with a full snapshot
+                    s.acked = None;
                 }
             }
-        }
-
-        for (i, h) in &health_map {
-            self.players
-                .entry(*i)
-                .and_modify(|p| p.health = p.health.saturating_sub(*h));
-        }
-        for (i, h) in &health_add {
-            self.players
-                .entry(*i)
-                .and_modify(|p| p.health = p.health.saturating_add(*h));
-        }
-        self.bullets.retain(|b| !delete_bullets.contains(&b.id));
-        self.boss_bullets
-            .retain(|b| !delete_boss_bullets.contains(&b.id));
-    }
-    fn reap_players(&mut self) {
-        let mut delete = Vec::new();
-        self.players.retain(|i, p| {
-            if p.health == 0 {
-                delete.push(*i);
-                false
-            } else {
-                true
+            let baseline = Baseline::new(&view);
+            let message = match &s.acked {
+                Some((base, b)) => ServerMessage::Delta(view.delta(*base, b)),
+                None => ServerMessage::Playfield(view),
+            };
+            s.addr.do_send(Message(message));
+            s.unacked.push_back((self.seq, baseline));
+            if s.unacked.len() > MAX_DELTA_AGE {
+                s.unacked.pop_front();
             }
-        });
-        for p in &delete {
-            self.send_message(
-                &json!({
-                    "death": p,
-                })
-                .to_string(),
-            )
         }
     }
 }
@@ -457,6 +462,13 @@ impl Actor for GameServer {
     fn started(&mut self, ctx: &mut Self::Context) {
         self.tick(ctx);
     }
+
+    fn stopped(&mut self, _: &mut Self::Context) {
+        self.record(Record::End);
+        if let Some(recorder) = &mut self.recorder {
+            let _ = recorder.flush();
+        }
+    }
 }
 
 /// Handler for Connect message.
@@ -466,20 +478,10 @@ impl Handler<Connect> for GameServer {
     type Result = usize;
 
     fn handle(&mut self, msg: Connect, _: &mut Context<Self>) -> Self::Result {
-        // register session with random id
-        let id = self.rng.gen::<usize>();
-        for w in &self.wormholes {
-            msg.addr.do_send(Message(
-                "{\"wormhole\":".to_owned()
-                    + &::serde_json::to_string(&ClientWormhole {
-                        pos: w.pos,
-                        color: w.color,
-                    })
-                    .unwrap()
-                    + "}",
-            ));
-        }
-        self.sessions.insert(id, msg.addr);
+        // register session with a fresh id
+        let id = next_id();
+        self.send_arena(&msg.addr);
+        self.sessions.insert(id, Session::new(msg.addr));
 
         // send id back
         id
@@ -492,28 +494,28 @@ impl Handler<Transfer> for GameServer {
     fn handle(&mut self, msg: Transfer, _: &mut Context<Self>) -> Self::Result {
         // msg.2.mana = 255;
         // msg.2.health = 255;
-        // msg.2.shot_time = Instant::now() - Duration::from_secs(2);
-        // msg.2.split_time = Instant::now() - Duration::from_secs(2);
+        // msg.2.shot_cooldown = 0;
+        // msg.2.split_cooldown = 0;
+
+        self.queue(Record::Arrive(msg.2));
+        msg.1.addr.do_send(Message(ServerMessage::Clear {}));
+        self.send_arena(&msg.1.addr);
+        // Snapshots from the old server are no use as a delta base here,
+        // but the client keeps numbering its inputs the same way
+        let mut session = Session::new(msg.1.addr);
+        session.last_input = msg.1.last_input;
+        self.sessions.insert(msg.0, session);
+    }
+}
 
-        self.players.insert(msg.0, msg.2);
-        msg.1.do_send(Message(
-            json!({
-                "clear": true
-            })
-            .to_string(),
-        ));
-        for w in &self.wormholes {
-            msg.1.do_send(Message(
-                json!({
-                    "wormhole": ClientWormhole {
-                        pos: w.pos,
-                        color: w.color,
-                    }
-                })
-                .to_string(),
-            ));
-        }
-        self.sessions.insert(msg.0, msg.1);
+impl Handler<TransferBot> for GameServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: TransferBot, _: &mut Context<Self>) -> Self::Result {
+        let TransferBot(mut bot, p) = msg;
+        self.queue(Record::Arrive(p));
+        bot.arrived();
+        self.bots.insert(bot.id, bot);
     }
 }
 
@@ -521,25 +523,9 @@ impl Handler<NewWormhole> for GameServer {
     type Result = ();
 
     fn handle(&mut self, msg: NewWormhole, _: &mut Context<Self>) -> Self::Result {
-        let b1 = if self.rng.gen::<bool>() {
-            WORLDSIZE
-        } else {
-            0.0
-        };
-        let b2 = self.rng.gen::<bool>();
-        let pos = self.rng.gen_range(0.0..WORLDSIZE);
-        let pos = Vector2::new(if b2 { b1 } else { pos }, if b2 { pos } else { b1 });
-        self.wormholes.push(Wormhole {
-            pos,
-            addr: msg.0,
-            color: msg.1,
-        });
-
-        self.send_message(
-            &("{\"wormhole\":".to_owned()
-                + &::serde_json::to_string(&ClientWormhole { pos, color: msg.1 }).unwrap()
-                + "}"),
-        )
+        // Clients hear of it once it is opened with the next step
+        self.queue(Record::Wormhole(msg.1));
+        self.wormholes.push(msg.0);
     }
 }
 
@@ -549,14 +535,45 @@ impl Handler<Disconnect> for GameServer {
 
     fn handle(&mut self, msg: Disconnect, _: &mut Context<Self>) {
         // remove address
-        self.sessions.remove(&msg.id);
-        self.players.remove(&msg.id);
-        self.send_message(
-            &json!({
-                "death": msg.id
-            })
-            .to_string(),
-        );
+        let session = self.sessions.remove(&msg.id);
+        if self.playback.is_some() {
+            return;
+        }
+        // Keep the session and player around in case the client comes back
+        if let Some(session) = session {
+            let p = self.world.players.get(&msg.id).cloned();
+            self.parked.insert(msg.id, (session, p, Instant::now()));
+        }
+        self.queue(Record::Leave(msg.id));
+        self.send_message(ServerMessage::Death(msg.id));
+    }
+}
+
+impl Handler<Resume> for GameServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Resume, _: &mut Context<Self>) {
+        msg.addr.do_send(Message(ServerMessage::Clear {}));
+        self.send_arena(&msg.addr);
+        // A live session is taken over as it is, a dropped one gets its
+        // player back
+        let live = self.sessions.remove(&msg.id);
+        let mut session = match (live, self.parked.remove(&msg.id)) {
+            (Some(session), _) => session,
+            (None, Some((session, p, _))) => {
+                if let Some(p) = p {
+                    self.queue(Record::Arrive(p));
+                }
+                session
+            }
+            (None, None) => Session::new(msg.addr.clone()),
+        };
+        // The client keeps numbering its inputs the same way, but drops its
+        // snapshots with the `Clear`, so deltas start over from a full one
+        session.addr = msg.addr;
+        session.acked = None;
+        session.unacked.clear();
+        self.sessions.insert(msg.id, session);
     }
 }
 
@@ -564,42 +581,34 @@ impl Handler<DecodedMessage> for GameServer {
     type Result = ();
 
     fn handle(&mut self, msg: DecodedMessage, _: &mut Context<Self>) {
-        if let ClientMessage::Spawn(n, c) = msg.m {
-            let x = self.rng.gen_range(0.0..WORLDSIZE);
-            let y = self.rng.gen_range(0.0..WORLDSIZE);
-
-            let p = Player {
-                id: msg.id,
-                vel: Vector2::new(0.0, 0.0),
-                pos: Vector2::new(x, y),
-                shot_time: Instant::now() - Duration::from_secs(2),
-                split_time: Instant::now() - Duration::from_secs(2),
-                escape_time: None,
-                target: Vector2::new(0.0, 0.0),
-                health: 255,
-                mana: 255,
-                name: n,
-                class: c,
-                mouse: false,
-                split: false,
-                join: false,
-            };
-            self.players.insert(msg.id, p);
-        } else if let Some(p) = self.players.get_mut(&msg.id) {
-            match msg.m {
-                ClientMessage::Click(b) => p.mouse = b,
-                ClientMessage::Split(b) => p.split = b,
-                ClientMessage::Target(v) => p.target = v,
-                ClientMessage::Escape(b) => {
-                    if !b {
-                        p.escape_time = None
-                    } else if p.escape_time.is_none() {
-                        p.escape_time = Some(Instant::now())
-                    }
+        if let ClientMessage::Ack(seq) = msg.m {
+            if let Some(s) = self.sessions.get_mut(&msg.id) {
+                s.ack(seq);
+            }
+        } else if self.playback.is_none() {
+            if let Some(input) = msg.m.into_input() {
+                self.queue(Record::Input(msg.id, input));
+            }
+            if let Some(s) = self.sessions.get_mut(&msg.id) {
+                s.last_active = Instant::now();
+                // Inputs are applied before the next step, so every snapshot
+                // from then on accounts for it
+                if let Some(seq) = msg.seq {
+                    s.last_input = seq;
                 }
-                ClientMessage::Join(b) => p.join = b,
-                ClientMessage::Spawn(_, _) => unreachable!(),
             }
         }
     }
 }
+
+impl Handler<Latency> for GameServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Latency, _: &mut Context<Self>) {
+        if self.playback.is_some() {
+            return;
+        }
+        let ticks = ticks(msg.rtt.as_millis() as u64);
+        self.queue(Record::Latency(msg.id, ticks));
+    }
+}
@@ -0,0 +1,348 @@
+//! Websocket sessions, one per connected client.
+//!
+//! A `WsGameSession` waits for the client's hello, joins the home
+//! `GameServer` (or takes over a session by its token) and from then on
+//! forwards inputs to whichever `GameServer` its player is in, encoding what
+//! comes back. A connection that went quiet may not have timed out yet when
+//! its client comes back, so tokens of live sessions can be taken over too.
+use actix::*;
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use actix_web_actors::ws;
+use s3_sim::class::Classes;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::consts;
+use crate::protocol::{ClientHello, ClientInput, Encoding, ServerMessage, Welcome, VERSION};
+use crate::server::{
+    Connect, DecodedMessage, Disconnect, GameServer, Latency, Message, Resume, TransferClient,
+};
+
+/// A session that dropped, which a new connection can take over with its token
+pub struct Dropped {
+    id: usize,
+    addr: Addr<GameServer>,
+    at: Instant,
+}
+
+/// A session a new connection can take over
+pub enum Resumable {
+    /// Still connected through this session, which hands its player over
+    Live(Addr<WsGameSession>),
+    Dropped(Dropped),
+}
+
+/// Live and recently dropped sessions by resume token
+pub type Resumes = Mutex<HashMap<String, Resumable>>;
+
+/// Hand the player over to another connection and close this one. Answers
+/// with the player's id and the `GameServer` it is in, unless the session
+/// has no player to hand over.
+#[derive(Message)]
+#[rtype(result = "Option<(usize, Addr<GameServer>)>")]
+struct Handover {
+    addr: Addr<WsGameSession>,
+}
+
+/// How long a client may stay silent, pongs included, before it is dropped
+#[derive(Clone, Copy)]
+pub struct HeartbeatTimeout(pub Duration);
+
+/// Entry point for our route
+pub async fn game_route(
+    req: HttpRequest,
+    stream: web::Payload,
+    srv: web::Data<Addr<GameServer>>,
+    heartbeat: web::Data<HeartbeatTimeout>,
+    resumes: web::Data<Resumes>,
+    classes: web::Data<Classes>,
+) -> Result<HttpResponse, Error> {
+    ws::start(
+        WsGameSession {
+            id: 0,
+            token: String::new(),
+            encoding: Encoding::Json,
+            addr: srv.get_ref().clone(),
+            started: Instant::now(),
+            heartbeat: Instant::now(),
+            heartbeat_timeout: heartbeat.0,
+            resumes: resumes.clone(),
+            classes: classes.clone(),
+        },
+        &req,
+        stream,
+    )
+}
+
+pub struct WsGameSession {
+    /// unique session id, 0 until the client said hello
+    id: usize,
+
+    /// secret the client can reconnect with to take this session over
+    token: String,
+
+    /// encoding used for snapshots
+    encoding: Encoding,
+
+    addr: Addr<GameServer>,
+
+    /// when the session started, pings carry the time since
+    started: Instant,
+
+    /// when the client last sent anything
+    heartbeat: Instant,
+
+    /// how long the client may stay silent
+    heartbeat_timeout: Duration,
+
+    /// where the session is left for a reconnect when it drops
+    resumes: web::Data<Resumes>,
+
+    /// classes the client can pick from
+    classes: web::Data<Classes>,
+}
+
+impl WsGameSession {
+    /// Tell the client who it is and how to come back
+    fn welcome(&self, ctx: &mut ws::WebsocketContext<Self>) {
+        self.resumes
+            .lock()
+            .unwrap()
+            .insert(self.token.clone(), Resumable::Live(ctx.address()));
+        let welcome = Welcome::new(self.encoding, self.id, self.token.clone(), &self.classes);
+        self.send(ServerMessage::Welcome(welcome), ctx);
+    }
+
+    /// Encode a message the way the client asked for
+    fn send(&self, message: ServerMessage, ctx: &mut ws::WebsocketContext<Self>) {
+        match (message, self.encoding) {
+            (ServerMessage::Playfield(p), Encoding::Binary) => ctx.binary(p.to_binary()),
+            (ServerMessage::Delta(d), Encoding::Binary) => ctx.binary(d.to_binary()),
+            (m, _) => ctx.text(serde_json::to_string(&m).unwrap()),
+        }
+    }
+
+    /// Close the connection, telling the client why
+    fn reject(&self, ctx: &mut ws::WebsocketContext<Self>, reason: String) {
+        println!("Rejected a client: {}", reason);
+        ctx.close(Some(ws::CloseReason {
+            code: ws::CloseCode::Protocol,
+            description: Some(reason),
+        }));
+        ctx.stop();
+    }
+
+    /// Handle the first message of the client, and join the game if it is a
+    /// hello we can serve
+    fn hello(&mut self, text: &str, ctx: &mut ws::WebsocketContext<Self>) {
+        let (version, encodings, resume) = match serde_json::from_str(text) {
+            Ok(ClientHello::Hello {
+                version,
+                encodings,
+                resume,
+            }) => (version, encodings, resume),
+            Err(_) => return self.reject(ctx, "expected a Hello message".to_owned()),
+        };
+        if version != VERSION {
+            let reason = format!(
+                "protocol version {} is not supported, the server speaks {}",
+                version, VERSION
+            );
+            return self.reject(ctx, reason);
+        }
+        self.encoding = match encodings.iter().find_map(|e| Encoding::from_name(e)) {
+            Some(encoding) => encoding,
+            None => {
+                return self.reject(ctx, "no supported encoding, use json or binary".to_owned())
+            }
+        };
+
+        match resume {
+            Some(token) => self.resume(token, ctx),
+            None => self.connect(ctx),
+        }
+    }
+
+    /// Take over the session with `token`, or join as a new one if it is
+    /// gone
+    fn resume(&mut self, token: String, ctx: &mut ws::WebsocketContext<Self>) {
+        let addr = ctx.address();
+        let found = self.resumes.lock().unwrap().remove(&token);
+        match found {
+            Some(Resumable::Live(live)) => {
+                live.send(Handover { addr })
+                    .into_actor(self)
+                    .then(move |res, act, ctx| {
+                        match res {
+                            Ok(Some((id, addr))) => {
+                                act.id = id;
+                                act.token = token;
+                                act.addr = addr;
+                                act.welcome(ctx);
+                            }
+                            Ok(None) => act.connect(ctx),
+                            // It closed meanwhile and left the session for us
+                            Err(_) => act.resume(token, ctx),
+                        }
+                        fut::ready(())
+                    })
+                    .wait(ctx);
+            }
+            Some(Resumable::Dropped(d)) if d.at.elapsed() < consts::RESUME_GRACE => {
+                self.id = d.id;
+                self.token = token;
+                self.addr = d.addr;
+                self.addr.do_send(Resume { id: self.id, addr });
+                self.welcome(ctx);
+            }
+            _ => self.connect(ctx),
+        }
+    }
+
+    /// Join the home server as a new session
+    fn connect(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
+        let addr = ctx.address();
+        self.token = format!("{:032x}", rand::random::<u128>());
+        // register self in game server. `AsyncContext::wait` register
+        // future within context, but context waits until this future resolves
+        // before processing any other events.
+        self.addr
+            .send(Connect { addr })
+            .into_actor(self)
+            .then(|res, act, ctx| {
+                match res {
+                    Ok(res) => {
+                        act.id = res;
+                        act.welcome(ctx);
+                    }
+                    // something is wrong with game server
+                    _ => ctx.stop(),
+                }
+                fut::ready(())
+            })
+            .wait(ctx);
+    }
+}
+
+impl Actor for WsGameSession {
+    type Context = ws::WebsocketContext<Self>;
+
+    /// Method is called on actor start.
+    /// The session joins the game server once the client said hello
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(consts::PING_INTERVAL, |act, ctx| {
+            if act.heartbeat.elapsed() > act.heartbeat_timeout {
+                // The connection is dead, `stopping` tells the game server
+                println!("Heartbeat timed out for session {}", act.id);
+                ctx.stop();
+                return;
+            }
+            let sent = act.started.elapsed().as_millis() as u64;
+            ctx.ping(&sent.to_le_bytes());
+        });
+    }
+
+    fn stopping(&mut self, _: &mut Self::Context) -> Running {
+        if self.id == 0 {
+            return Running::Stop;
+        }
+        // notify game server
+        self.addr.do_send(Disconnect { id: self.id });
+        let mut resumes = self.resumes.lock().unwrap();
+        resumes.retain(|_, r| match r {
+            Resumable::Live(_) => true,
+            Resumable::Dropped(d) => d.at.elapsed() < consts::RESUME_GRACE,
+        });
+        resumes.insert(
+            self.token.clone(),
+            Resumable::Dropped(Dropped {
+                id: self.id,
+                addr: self.addr.clone(),
+                at: Instant::now(),
+            }),
+        );
+        Running::Stop
+    }
+}
+
+impl Handler<Handover> for WsGameSession {
+    type Result = Option<(usize, Addr<GameServer>)>;
+
+    fn handle(&mut self, msg: Handover, ctx: &mut Self::Context) -> Self::Result {
+        if self.id == 0 {
+            return None;
+        }
+        // The game server swaps the connection, this one leaves quietly
+        self.addr.do_send(Resume {
+            id: self.id,
+            addr: msg.addr,
+        });
+        let id = std::mem::replace(&mut self.id, 0);
+        ctx.close(Some(ws::CloseReason {
+            code: ws::CloseCode::Normal,
+            description: Some("the session was resumed elsewhere".to_owned()),
+        }));
+        ctx.stop();
+        Some((id, self.addr.clone()))
+    }
+}
+
+/// Handle messages from game server, we encode them and send them to peer WebSocket
+impl Handler<Message> for WsGameSession {
+    type Result = ();
+
+    fn handle(&mut self, msg: Message, ctx: &mut Self::Context) {
+        self.send(msg.0, ctx);
+    }
+}
+
+impl Handler<TransferClient> for WsGameSession {
+    type Result = ();
+
+    fn handle(&mut self, msg: TransferClient, _: &mut Self::Context) {
+        self.addr = msg.0;
+    }
+}
+
+/// WebSocket message handler
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsGameSession {
+    fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        self.heartbeat = Instant::now();
+        match msg {
+            Ok(ws::Message::Ping(msg)) => ctx.pong(&msg),
+            // Latency only matters once the session is in the game
+            Ok(ws::Message::Pong(_)) if self.id == 0 => (),
+            Ok(ws::Message::Pong(msg)) => {
+                if let Ok(sent) = msg[..].try_into() {
+                    let sent = Duration::from_millis(u64::from_le_bytes(sent));
+                    if let Some(rtt) = self.started.elapsed().checked_sub(sent) {
+                        self.addr.do_send(Latency { id: self.id, rtt });
+                    }
+                }
+            }
+            Ok(ws::Message::Text(text)) if self.id == 0 => self.hello(text.trim(), ctx),
+            Ok(ws::Message::Text(text)) => {
+                // All the client sends are key messages so we assume that the message is a key message
+                if let Ok(ClientInput { seq, m }) = serde_json::from_str(text.trim()) {
+                    self.addr.do_send(DecodedMessage {
+                        id: self.id,
+                        seq,
+                        m,
+                    });
+                }
+
+                // send message to game server
+            }
+            Ok(ws::Message::Binary(_)) if self.id == 0 => {
+                self.reject(ctx, "expected a Hello message".to_owned())
+            }
+            Ok(ws::Message::Binary(_)) => println!("Unexpected binary"),
+            Ok(ws::Message::Close(_)) => {
+                ctx.stop();
+            }
+            _ => (),
+        }
+    }
+}
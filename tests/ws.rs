@@ -0,0 +1,703 @@
+//! Drives game servers over real websockets, the way the client does.
+use actix::{Actor, Addr};
+use futures_util::{SinkExt, StreamExt};
+use nalgebra::Vector2;
+use serde_json::{json, Value};
+use std::net::TcpListener;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+
+use s3::protocol::{KIND_PLAYFIELD, VERSION};
+use s3::server::{GameServer, NewWormhole};
+use s3::session::HeartbeatTimeout;
+use s3_sim::boss::{BossClass, Bosses};
+use s3_sim::bot::Difficulty;
+use s3_sim::class::Classes;
+use s3_sim::map::{Geometry, Map, Zone};
+use s3_sim::obstacle::{Obstacle, Shape};
+use s3_sim::replay::{Header, Record, Recorder};
+use s3_sim::Input;
+
+/// Start a home server with a wormhole to a boss server, like `main` does,
+/// and serve it on a free port
+fn start() -> u16 {
+    let home = GameServer::new(Map::default(), None, None).start();
+    let boss = GameServer::new(
+        Map::default(),
+        Bosses::default().get("Normal").cloned(),
+        Some(home.clone()),
+    )
+    .start();
+    home.do_send(NewWormhole(boss, 1));
+    serve(home, Classes::default())
+}
+
+/// Serve an arena on `map` with `classes` on a free port, after `setup`.
+/// With a `boss`, the arena is a boss server that players leave for a home one
+fn serve_arena(
+    map: Map,
+    boss: Option<&BossClass>,
+    classes: Classes,
+    setup: impl FnOnce(GameServer) -> GameServer,
+) -> u16 {
+    let home = boss.map(|_| GameServer::new(Map::default(), None, None).start());
+    let arena = GameServer::new(map, boss.cloned(), home).with_classes(classes.clone());
+    serve(setup(arena).start(), classes)
+}
+
+/// Serve `home`, which has `classes`, on a free port
+fn serve(home: Addr<GameServer>, classes: Classes) -> u16 {
+    serve_with_heartbeat(home, classes, Duration::from_secs(10))
+}
+
+/// Serve `home` on a free port, dropping clients silent for `heartbeat`
+fn serve_with_heartbeat(home: Addr<GameServer>, classes: Classes, heartbeat: Duration) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let heartbeat = HeartbeatTimeout(heartbeat);
+    actix_web::rt::spawn(s3::serve(listener, home, heartbeat, classes).unwrap());
+    port
+}
+
+/// A scripted client speaking JSON
+struct Client {
+    ws: WebSocketStream<MaybeTlsStream<TcpStream>>,
+}
+
+impl Client {
+    async fn connect(port: u16) -> Client {
+        let (ws, _) = connect_async(format!("ws://127.0.0.1:{}/ws", port))
+            .await
+            .unwrap();
+        Client { ws }
+    }
+
+    /// Connect and say hello, returns the client and its welcome
+    async fn join(port: u16) -> (Client, Value) {
+        Client::hello(port, "json").await
+    }
+
+    /// Connect and say hello asking for `encoding`
+    async fn hello(port: u16, encoding: &str) -> (Client, Value) {
+        let mut client = Client::connect(port).await;
+        client
+            .send(json!({ "Hello": { "version": VERSION, "encodings": [encoding] } }))
+            .await;
+        let welcome = client.until(|m| m.get("welcome").is_some()).await;
+        (client, welcome["welcome"].clone())
+    }
+
+    async fn send(&mut self, m: Value) {
+        self.ws.send(WsMessage::Text(m.to_string())).await.unwrap();
+    }
+
+    /// The next message from the server, `None` once the socket closes
+    async fn recv(&mut self) -> Option<WsMessage> {
+        tokio::time::timeout(Duration::from_secs(30), self.ws.next())
+            .await
+            .expect("timed out waiting for the server")
+            .map(Result::unwrap)
+    }
+
+    /// Skip messages until one matches `f`
+    async fn until(&mut self, mut f: impl FnMut(&Value) -> bool) -> Value {
+        loop {
+            match self.recv().await {
+                Some(WsMessage::Text(text)) => {
+                    let m: Value = serde_json::from_str(&text).unwrap();
+                    if f(&m) {
+                        return m;
+                    }
+                }
+                Some(_) => (),
+                None => panic!("the server closed the connection"),
+            }
+        }
+    }
+
+    /// Wait for a snapshot with the player `id` in it, returns the snapshot
+    /// and the player
+    async fn until_player(&mut self, id: &Value) -> (Value, Value) {
+        let m = self
+            .until(|m| {
+                m["playfield"]["players"]
+                    .as_array()
+                    .is_some_and(|ps| ps.iter().any(|p| p["id"] == *id))
+            })
+            .await;
+        let p = m["playfield"]["players"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|p| p["id"] == *id)
+            .unwrap()
+            .clone();
+        (m["playfield"].clone(), p)
+    }
+}
+
+#[actix_web::test]
+async fn welcome_describes_the_server() {
+    let port = start();
+    let (_, welcome) = Client::join(port).await;
+
+    assert_eq!(welcome["version"], VERSION);
+    assert_eq!(welcome["encoding"], "json");
+    assert!(welcome.get("world_size").is_none());
+    assert_eq!(welcome["classes"].as_array().unwrap().len(), 2);
+    assert!(welcome["you"].as_u64().unwrap() > 0);
+    assert!(!welcome["token"].as_str().unwrap().is_empty());
+}
+
+#[actix_web::test]
+async fn binary_clients_get_binary_snapshots() {
+    let port = start();
+    let (mut client, welcome) = Client::hello(port, "binary").await;
+    assert_eq!(welcome["encoding"], "binary");
+    client.send(json!({ "Spawn": ["alice", "Sniper"] })).await;
+
+    // A full snapshot with the player in it, ids being the first field of
+    // each player
+    let varint = |frame: &mut &[u8]| {
+        let mut v = 0u64;
+        for shift in (0..).step_by(7) {
+            let (&byte, rest) = frame.split_first().unwrap();
+            *frame = rest;
+            v |= ((byte & 0x7f) as u64) << shift;
+            if byte < 0x80 {
+                break;
+            }
+        }
+        v
+    };
+    loop {
+        let frame = match client.recv().await {
+            Some(WsMessage::Binary(frame)) => frame,
+            Some(_) => continue,
+            None => panic!("the server closed the connection"),
+        };
+        assert_eq!(frame[..2], [VERSION, KIND_PLAYFIELD]);
+        let mut rest = &frame[2..];
+        let seq = varint(&mut rest);
+        let tick = varint(&mut rest);
+        let _input = varint(&mut rest);
+        let _flags = rest[0];
+        rest = &rest[1..];
+        assert!(seq > 0 && tick > 0);
+        if varint(&mut rest) == 1 {
+            assert_eq!(varint(&mut rest), welcome["you"].as_u64().unwrap());
+            break;
+        }
+    }
+}
+
+#[actix_web::test]
+async fn incompatible_clients_are_rejected() {
+    let port = start();
+    let mut client = Client::connect(port).await;
+    client
+        .send(json!({ "Hello": { "version": 0, "encodings": ["json"] } }))
+        .await;
+
+    loop {
+        match client.recv().await {
+            Some(WsMessage::Close(Some(frame))) => {
+                assert_eq!(frame.code, CloseCode::Protocol);
+                assert!(frame.reason.contains("protocol version"));
+                break;
+            }
+            Some(_) => (),
+            None => panic!("closed without a reason"),
+        }
+    }
+}
+
+#[actix_web::test]
+async fn spawned_players_shoot() {
+    let port = start();
+    let (mut client, welcome) = Client::join(port).await;
+    client
+        .send(json!({ "seq": 1, "Spawn": ["alice", "Sniper"] }))
+        .await;
+
+    let (playfield, p) = client.until_player(&welcome["you"]).await;
+    assert_eq!(p["name"], "alice");
+    assert_eq!(p["class"], "Sniper");
+    assert_eq!(playfield["input"], 1);
+
+    client.send(json!({ "seq": 2, "Click": true })).await;
+    let m = client
+        .until(|m| {
+            m["playfield"]["bullets"]
+                .as_array()
+                .is_some_and(|b| !b.is_empty())
+        })
+        .await;
+    assert_eq!(m["playfield"]["input"], 2);
+}
+
+#[actix_web::test]
+async fn acknowledged_snapshots_are_followed_by_deltas() {
+    let port = start();
+    let (mut client, _) = Client::join(port).await;
+    let m = client.until(|m| m.get("playfield").is_some()).await;
+    let seq = m["playfield"]["seq"].clone();
+
+    client.send(json!({ "Ack": seq })).await;
+    let m = client.until(|m| m.get("delta").is_some()).await;
+    assert_eq!(m["delta"]["base"], seq);
+}
+
+#[actix_web::test]
+async fn snapshots_only_show_what_is_in_view() {
+    let bosses = Bosses::parse(
+        r#"
+        [[boss]]
+        name = "Rock"
+
+        [[boss.phases]]
+        health = 255
+        movement = { kind = "hold" }
+        "#,
+    )
+    .unwrap();
+    // Two spawn points far further apart than the default view is wide
+    let map = Map {
+        size: Vector2::new(5000.0, 5000.0),
+        spawns: vec![Vector2::new(500.0, 500.0), Vector2::new(4500.0, 4500.0)],
+        boss_zones: vec![Zone {
+            pos: Vector2::new(4500.0, 500.0),
+            radius: 0.0,
+        }],
+        ..Map::default()
+    };
+    let port = serve_arena(map, bosses.get("Rock"), Classes::default(), |s| s);
+    let (mut alice, welcome) = Client::join(port).await;
+    let (mut bob, _) = Client::join(port).await;
+    for client in [&mut alice, &mut bob] {
+        client.send(json!({ "Spawn": ["sniper", "Sniper"] })).await;
+        client.send(json!({ "Click": true })).await;
+    }
+
+    // Once both are shooting, alice sees herself, her bullets and the boss,
+    // but neither bob nor his bullets
+    let bullets = |m: &Value| -> Vec<Value> {
+        m["playfield"]["bullets"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|b| b["id"].clone())
+            .collect()
+    };
+    loop {
+        let a = alice.until(|m| m.get("playfield").is_some()).await;
+        let tick = a["playfield"]["tick"].clone();
+        let b = bob.until(|m| m["playfield"]["tick"] == tick).await;
+        let (seen, shot) = (bullets(&a), bullets(&b));
+        if seen.is_empty() || shot.is_empty() {
+            continue;
+        }
+        let players = a["playfield"]["players"].as_array().unwrap();
+        assert_eq!(players.len(), 1);
+        assert_eq!(players[0]["id"], welcome["you"]);
+        assert!(a["playfield"]["boss"].is_object());
+        assert!(shot.iter().all(|id| !seen.contains(id)));
+        break;
+    }
+}
+
+#[actix_web::test]
+async fn players_that_leave_are_announced() {
+    let port = start();
+    let (mut alice, welcome) = Client::join(port).await;
+    let (mut bob, _) = Client::join(port).await;
+    let id = welcome["you"].clone();
+
+    alice.send(json!({ "Spawn": ["alice", "Quickshot"] })).await;
+    alice.until_player(&id).await;
+
+    alice.ws.close(None).await.unwrap();
+    bob.until(|m| m["death"] == id).await;
+}
+
+#[actix_web::test]
+async fn silent_clients_are_dropped() {
+    let home = GameServer::new(Map::default(), None, None)
+        .with_view_size(Vector2::new(4000.0, 4000.0))
+        .start();
+    let port = serve_with_heartbeat(home, Classes::default(), Duration::from_secs(3));
+    let (mut alice, welcome) = Client::join(port).await;
+    let (mut bob, _) = Client::join(port).await;
+    alice.send(json!({ "Spawn": ["alice", "Sniper"] })).await;
+    bob.until_player(&welcome["you"]).await;
+
+    // Alice stops reading, so her pings go unanswered, while bob keeps
+    // answering his
+    bob.until(|m| m["death"] == welcome["you"]).await;
+    drop(alice);
+}
+
+#[actix_web::test]
+async fn idle_players_are_kicked() {
+    let port = serve_arena(Map::default(), None, Classes::default(), |s| {
+        s.with_afk_timeout(Duration::from_millis(500))
+    });
+    let (mut client, welcome) = Client::join(port).await;
+    client.send(json!({ "Spawn": ["alice", "Sniper"] })).await;
+    client.until_player(&welcome["you"]).await;
+
+    // The connection stays up, only the player is taken out
+    client.until(|m| m["death"] == welcome["you"]).await;
+    let gone = |m: &Value| {
+        m["playfield"]["players"]
+            .as_array()
+            .is_some_and(|ps| ps.iter().all(|p| p["id"] != welcome["you"]))
+    };
+    client.until(gone).await;
+    for _ in 0..30 {
+        let m = client.until(|m| m.get("playfield").is_some()).await;
+        assert!(gone(&m));
+    }
+}
+
+#[actix_web::test]
+async fn players_that_leave_as_they_spawn_are_gone() {
+    let port = serve_arena(Map::default(), None, Classes::default(), |s| {
+        s.with_view_size(Vector2::new(4000.0, 4000.0))
+    });
+    let (mut bob, _) = Client::join(port).await;
+    let (mut alice, welcome) = Client::join(port).await;
+    let id = welcome["you"].clone();
+
+    // The connection drops right after the spawn, in the same tick
+    alice.send(json!({ "Spawn": ["alice", "Sniper"] })).await;
+    drop(alice);
+    bob.until(|m| m["death"] == id).await;
+
+    for _ in 0..20 {
+        let m = bob.until(|m| m.get("playfield").is_some()).await;
+        let players = m["playfield"]["players"].as_array().unwrap();
+        assert!(
+            players.iter().all(|p| p["id"] != id),
+            "the player outlived its session"
+        );
+    }
+}
+
+#[actix_web::test]
+async fn sessions_are_resumed_before_they_time_out() {
+    let port = start();
+    let (mut old, welcome) = Client::join(port).await;
+    old.send(json!({ "seq": 1, "Spawn": ["alice", "Sniper"] }))
+        .await;
+    old.until_player(&welcome["you"]).await;
+
+    // The old connection has not timed out yet when the client comes back
+    let mut client = Client::connect(port).await;
+    client
+        .send(json!({ "Hello": {
+            "version": VERSION,
+            "encodings": ["json"],
+            "resume": welcome["token"],
+        } }))
+        .await;
+    let m = client.until(|m| m.get("welcome").is_some()).await;
+    assert_eq!(m["welcome"]["you"], welcome["you"]);
+    let (playfield, p) = client.until_player(&welcome["you"]).await;
+    assert_eq!(p["name"], "alice");
+    // The inputs the server applied before are still accounted for
+    assert_eq!(playfield["input"], 1);
+
+    // The new connection takes over and the old one is closed
+    loop {
+        match old.recv().await {
+            None | Some(WsMessage::Close(_)) => break,
+            _ => (),
+        }
+    }
+
+    // Once that one drops too, the client can still come back
+    client.ws.close(None).await.unwrap();
+    drop(client);
+    let mut client = Client::connect(port).await;
+    client
+        .send(json!({ "Hello": {
+            "version": VERSION,
+            "encodings": ["json"],
+            "resume": welcome["token"],
+        } }))
+        .await;
+    let (playfield, _) = client.until_player(&welcome["you"]).await;
+    assert_eq!(playfield["input"], 1);
+}
+
+#[actix_web::test]
+async fn wormholes_lead_to_the_boss_server() {
+    let port = start();
+    let (mut client, welcome) = Client::join(port).await;
+    let id = welcome["you"].clone();
+    let wormhole = client.until(|m| m.get("wormhole").is_some()).await;
+    let target = &wormhole["wormhole"]["pos"];
+    let (tx, ty) = (target[0].as_f64().unwrap(), target[1].as_f64().unwrap());
+
+    client.send(json!({ "Spawn": ["alice", "Sniper"] })).await;
+    client.send(json!({ "Join": true })).await;
+    // Steer into the wormhole until the home server hands us over
+    loop {
+        let m = client
+            .until(|m| m.get("clear").is_some() || m.get("playfield").is_some())
+            .await;
+        if m.get("clear").is_some() {
+            break;
+        }
+        let players = m["playfield"]["players"].as_array().unwrap();
+        if let Some(p) = players.iter().find(|p| p["id"] == id) {
+            let (x, y) = (p["pos"][0].as_f64().unwrap(), p["pos"][1].as_f64().unwrap());
+            client.send(json!({ "Target": [tx - x, ty - y] })).await;
+        }
+    }
+
+    let (playfield, p) = client.until_player(&id).await;
+    assert_eq!(p["name"], "alice");
+    assert!(playfield["boss"].is_object());
+}
+
+#[actix_web::test]
+async fn spectators_are_not_taken_for_recorded_players() {
+    let recorded = 5_000_000;
+    let path = std::env::temp_dir().join(format!("s3-spectate-{}.replay", std::process::id()));
+    let header = Header {
+        seed: 1,
+        boss: None,
+        escapable: false,
+        classes: Classes::default(),
+        map: Map::default(),
+    };
+    let mut recorder = Recorder::create(&path, &header).unwrap();
+    let spawn = Input::Spawn("alice".to_owned(), "Sniper".to_owned());
+    recorder.record(0, Record::Input(recorded, spawn)).unwrap();
+    recorder.record(60, Record::End).unwrap();
+    recorder.flush().unwrap();
+
+    let server = GameServer::replay(&path).unwrap().start();
+    std::fs::remove_file(&path).unwrap();
+    let port = serve(server, Classes::default());
+    let (mut client, welcome) = Client::join(port).await;
+    let you = welcome["you"].as_u64().unwrap();
+    assert!(you > recorded as u64, "the spectator is {}", you);
+    client.until_player(&json!(recorded)).await;
+}
+
+#[actix_web::test]
+async fn bots_keep_the_arena_populated() {
+    let port = serve_arena(Map::default(), None, Classes::default(), |s| {
+        s.with_view_size(Vector2::new(4000.0, 4000.0))
+            .with_bots(3, Difficulty::Hard)
+    });
+    let (mut client, _) = Client::join(port).await;
+
+    // With us connected, two bots are enough
+    let bots = |m: &Value| {
+        m["playfield"]["players"].as_array().map_or(0, |ps| {
+            ps.iter()
+                .filter(|p| p["name"].as_str().unwrap().ends_with("[bot]"))
+                .count()
+        })
+    };
+    let m = client.until(|m| bots(m) == 2).await;
+    assert_eq!(m["playfield"]["players"].as_array().unwrap().len(), 2);
+}
+
+#[actix_web::test]
+async fn servers_offer_the_classes_they_load() {
+    let classes = Classes::parse(
+        r#"
+        [[class]]
+        name = "Shotgun"
+        pattern = "fan"
+        cooldown = 500
+        bullets = 5
+        speed = 12.0
+        spread = 40.0
+        lifetime = 300
+        damage = 20
+        mana_regen = 20
+        "#,
+    )
+    .unwrap();
+    let port = serve_arena(Map::default(), None, classes, |s| s);
+    let (mut client, welcome) = Client::join(port).await;
+    assert_eq!(welcome["classes"].as_array().unwrap().len(), 1);
+    assert_eq!(welcome["classes"][0]["class"], "Shotgun");
+
+    // Classes the server doesn't have can't be spawned as
+    client.send(json!({ "Spawn": ["alice", "Sniper"] })).await;
+    client.send(json!({ "Spawn": ["alice", "Shotgun"] })).await;
+    let (_, p) = client.until_player(&welcome["you"]).await;
+    assert_eq!(p["class"], "Shotgun");
+
+    client.send(json!({ "Click": true })).await;
+    let m = client
+        .until(|m| {
+            m["playfield"]["bullets"]
+                .as_array()
+                .is_some_and(|b| !b.is_empty())
+        })
+        .await;
+    assert_eq!(m["playfield"]["bullets"].as_array().unwrap().len(), 5);
+}
+
+#[actix_web::test]
+async fn abilities_are_cast_from_their_slot() {
+    let port = start();
+    let (mut client, welcome) = Client::join(port).await;
+    let sniper = &welcome["classes"][1];
+    assert_eq!(sniper["class"], "Sniper");
+    assert_eq!(sniper["abilities"][1]["kind"], "blink");
+
+    client.send(json!({ "Spawn": ["alice", "Sniper"] })).await;
+    let (_, p) = client.until_player(&welcome["you"]).await;
+    assert_eq!(p["abilities"].as_array().unwrap().len(), 3);
+
+    client.send(json!({ "Ability": [1, true] })).await;
+    let p = loop {
+        let (_, p) = client.until_player(&welcome["you"]).await;
+        if p["abilities"][1]["cooldown"].as_u64().unwrap() > 0 {
+            break p;
+        }
+    };
+    // Only the blink was cast, and paid for
+    assert_eq!(p["abilities"][0]["cooldown"], 0);
+    assert!(p["mana"].as_u64().unwrap() < 255);
+}
+
+#[actix_web::test]
+async fn players_bump_into_each_other() {
+    let port = serve_arena(Map::default(), None, Classes::default(), |s| {
+        s.with_view_size(Vector2::new(4000.0, 4000.0))
+    });
+    let (mut a, wa) = Client::join(port).await;
+    let (mut b, wb) = Client::join(port).await;
+    a.send(json!({ "Spawn": ["a", "Sniper"] })).await;
+    b.send(json!({ "Spawn": ["b", "Sniper"] })).await;
+    b.until_player(&wb["you"]).await;
+
+    let pos =
+        |p: &Value| Vector2::new(p["pos"][0].as_f64().unwrap(), p["pos"][1].as_f64().unwrap());
+    let both = |m: &Value| {
+        let ps = m["playfield"]["players"].as_array()?;
+        let find = |id: &Value| ps.iter().find(|p| p["id"] == *id).map(pos);
+        Some((find(&wa["you"])?, find(&wb["you"])?))
+    };
+    let m = a.until(|m| both(m).is_some()).await;
+    let (pa, pb) = both(&m).unwrap();
+    // Fly at each other and keep pushing
+    a.send(json!({ "Target": [pb.x - pa.x, pb.y - pa.y] }))
+        .await;
+    b.send(json!({ "Target": [pa.x - pb.x, pa.y - pb.y] }))
+        .await;
+
+    let apart = |m: &Value| both(m).map(|(pa, pb)| (pa - pb).magnitude());
+    a.until(|m| apart(m).is_some_and(|d| d < 80.0)).await;
+    for _ in 0..30 {
+        let m = a.until(|m| apart(m).is_some()).await;
+        let d = apart(&m).unwrap();
+        assert!(d >= 69.0, "players overlap, {} apart", d);
+    }
+}
+
+#[actix_web::test]
+async fn obstacles_block_players() {
+    let rock = Obstacle {
+        pos: Vector2::new(800.0, 800.0),
+        shape: Shape::Circle(300.0),
+        model: "meteorFull".to_owned(),
+    };
+    let map = Map {
+        obstacles: vec![rock],
+        ..Map::default()
+    };
+    let port = serve_arena(map, None, Classes::default(), |s| s);
+    let (mut client, welcome) = Client::join(port).await;
+    let m = client.until(|m| m.get("obstacles").is_some()).await;
+    assert_eq!(m["obstacles"][0]["shape"]["circle"], 300.0);
+
+    client.send(json!({ "Spawn": ["alice", "Sniper"] })).await;
+    let (_, p) = client.until_player(&welcome["you"]).await;
+    let center = |p: &Value| {
+        Vector2::new(
+            800.0 - p["pos"][0].as_f64().unwrap(),
+            800.0 - p["pos"][1].as_f64().unwrap(),
+        )
+    };
+    let to = center(&p);
+    client.send(json!({ "Target": [to.x, to.y] })).await;
+
+    // Flying straight at the rock ends up against it, never inside
+    let mut p = p;
+    while center(&p).magnitude() > 340.0 {
+        p = client.until_player(&welcome["you"]).await.1;
+    }
+    for _ in 0..30 {
+        let p = client.until_player(&welcome["you"]).await.1;
+        let d = center(&p).magnitude();
+        assert!(
+            d >= 334.0,
+            "the player is inside the rock, {} from its center",
+            d
+        );
+    }
+}
+
+#[actix_web::test]
+async fn maps_lay_out_the_arena() {
+    let map = Map {
+        size: Vector2::new(1000.0, 700.0),
+        spawns: vec![Vector2::new(300.0, 200.0)],
+        ..Map::default()
+    };
+    let port = serve_arena(map, None, Classes::default(), |s| s);
+    let (mut client, welcome) = Client::join(port).await;
+    let m = client.until(|m| m.get("arena").is_some()).await;
+    assert_eq!(m["arena"]["size"], json!([1000.0, 700.0]));
+
+    client.send(json!({ "Spawn": ["alice", "Sniper"] })).await;
+    let (_, p) = client.until_player(&welcome["you"]).await;
+    let pos = Vector2::new(p["pos"][0].as_f64().unwrap(), p["pos"][1].as_f64().unwrap());
+    let d = (pos - Vector2::new(300.0, 200.0)).magnitude();
+    assert!(d < 30.0, "the player spawned {} from the spawn point", d);
+}
+
+#[actix_web::test]
+async fn torus_arenas_wrap_around() {
+    let map = Map {
+        size: Vector2::new(1000.0, 1000.0),
+        geometry: Geometry::Torus,
+        spawns: vec![Vector2::new(900.0, 500.0)],
+        ..Map::default()
+    };
+    let port = serve_arena(map, None, Classes::default(), |s| s);
+    let (mut client, welcome) = Client::join(port).await;
+    let m = client.until(|m| m.get("arena").is_some()).await;
+    assert_eq!(m["arena"]["geometry"], "torus");
+
+    client.send(json!({ "Spawn": ["alice", "Sniper"] })).await;
+    client.send(json!({ "Target": [100.0, 0.0] })).await;
+
+    // Flying right off the edge comes back in on the left
+    loop {
+        let (_, p) = client.until_player(&welcome["you"]).await;
+        let x = p["pos"][0].as_f64().unwrap();
+        assert!(
+            (0.0..=1000.0).contains(&x),
+            "the player left the arena at {}",
+            x
+        );
+        if x < 200.0 {
+            break;
+        }
+    }
+}